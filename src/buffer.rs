@@ -0,0 +1,247 @@
+use crate::editor::{Editor, EditorRow};
+use crate::encoding::Encoding;
+use crate::file_io::FileFormat;
+use crate::input::QUIT_TIMES;
+use crate::rope::RopeNode;
+use crate::selection::Selection;
+use crate::syntax::Syntax;
+use crate::undo::UndoStack;
+use crate::window::View;
+
+use std::path::Path;
+use std::rc::Rc;
+use anyhow::Result;
+
+// A file being edited. Windows show buffers by their index in
+// `Editor::buffers`; the focused window's buffer is the current one.
+pub struct Buffer {
+    pub rows: RopeNode<EditorRow>,
+    pub dirty: bool,
+    pub undo: UndoStack,
+    pub file_name: String,
+    pub format: FileFormat,
+    pub read_only: bool,
+    pub syntax: Option<Rc<Syntax>>,
+    pub selection: Option<Selection>,
+    pub view: View, // where the cursor was when the buffer was last left
+}
+
+impl Buffer {
+    pub fn new(read_only: bool) -> Self {
+        Self {
+            rows: RopeNode::default(),
+            dirty: false,
+            undo: UndoStack::new(),
+            file_name: String::new(),
+            format: FileFormat::default(),
+            read_only,
+            syntax: None,
+            selection: None,
+            view: View::default(),
+        }
+    }
+
+    fn name(&self) -> &str {
+        if self.file_name.is_empty() { "[No Name]" } else { &self.file_name }
+    }
+}
+
+impl Editor {
+    pub fn current_buffer(&self) -> usize {
+        self.windows[self.current_window].buffer
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffers[self.current_buffer()]
+    }
+
+    pub fn buffer_mut(&mut self) -> &mut Buffer {
+        let i = self.current_buffer();
+        &mut self.buffers[i]
+    }
+
+    // Show buffer `i` in the focused window, where the cursor was when it
+    // was last left.
+    pub fn switch_buffer(&mut self, i: usize) {
+        if i == self.current_buffer() {
+            return;
+        }
+        let view = self.view();
+        self.buffer_mut().view = view;
+        self.windows[self.current_window].buffer = i;
+        self.set_view(self.buffers[i].view);
+        self.search_hl = None;
+    }
+
+    pub fn next_buffer(&mut self, forward: bool) {
+        let count = self.buffers.len();
+        if count == 1 {
+            self.set_status_msg("No other buffers");
+            return;
+        }
+        let i = if forward {
+            (self.current_buffer() + 1) % count
+        } else {
+            (self.current_buffer() + count - 1) % count
+        };
+        self.switch_buffer(i);
+        self.show_buffer_name();
+    }
+
+    fn show_buffer_name(&mut self) {
+        let msg = format!(
+            "[{}/{}] {}",
+            self.current_buffer() + 1,
+            self.buffers.len(),
+            self.buffer().name()
+        );
+        self.set_status_msg(&msg);
+    }
+
+    pub fn any_buffer_dirty(&self) -> bool {
+        self.buffers.iter().any(|buffer| buffer.dirty)
+    }
+
+    // Open `file_name` in a new buffer, or switch to it if it is open
+    // already. An untouched empty buffer is reused, and a file that does
+    // not exist yet is opened empty. Without an `encoding` it is detected.
+    pub fn open_buffer(&mut self, file_name: &str, encoding: Option<Encoding>) -> Result<()> {
+        if let Some(i) = self.buffers.iter().position(|buffer| buffer.file_name == file_name) {
+            self.switch_buffer(i);
+            return Ok(());
+        }
+        let previous = self.current_buffer();
+        let added = self.add_buffer();
+        if !Path::new(file_name).exists() {
+            let buffer = self.buffer_mut();
+            buffer.file_name = file_name.to_string();
+            if let Some(encoding) = encoding {
+                buffer.format.encoding = encoding;
+            }
+            self.select_syntax();
+            self.set_status_msg(&format!("{}: new file", file_name));
+            return Ok(());
+        }
+        if let Err(e) = self.open_file(file_name, encoding) {
+            if added {
+                self.switch_buffer(previous);
+                self.buffers.pop();
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    // Open text read from standard input in a new unnamed buffer.
+    pub fn open_stdin_buffer(&mut self, bytes: &[u8], encoding: Option<Encoding>) {
+        self.add_buffer();
+        self.load_bytes(bytes, encoding);
+    }
+
+    // Switch to a new empty buffer, unless the current one is untouched and
+    // empty. Returns whether a buffer was added.
+    fn add_buffer(&mut self) -> bool {
+        let buffer = self.buffer();
+        if buffer.file_name.is_empty() && !buffer.dirty && buffer.rows.count() == 0 {
+            return false;
+        }
+        self.buffers.push(Buffer::new(self.read_only));
+        self.switch_buffer(self.buffers.len() - 1);
+        true
+    }
+
+    pub fn open_buffer_prompt(&mut self) -> Result<()> {
+        let Some(file_name) = self.prompt("Open file", None)? else {
+            return Ok(());
+        };
+        if file_name.is_empty() {
+            return Ok(());
+        }
+        match self.open_buffer(&file_name, None) {
+            Ok(()) => self.show_buffer_name(),
+            Err(e) => self.set_status_msg(&format!("{:#}", e)),
+        }
+        Ok(())
+    }
+
+    // List the buffers in the prompt and switch to the one picked by number
+    // or by part of its name.
+    pub fn pick_buffer(&mut self) -> Result<()> {
+        let current = self.current_buffer();
+        let list: Vec<String> = self
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(i, buffer)| {
+                format!(
+                    "{}{}:{}{}",
+                    if i == current { "*" } else { "" },
+                    i + 1,
+                    buffer.name(),
+                    if buffer.dirty { "+" } else { "" }
+                )
+            })
+            .collect();
+        let label = format!("Buffer ({})", list.join(" "));
+        let Some(choice) = self.prompt(&label, None)? else {
+            return Ok(());
+        };
+        let choice = choice.trim();
+        if choice.is_empty() {
+            return Ok(());
+        }
+        let found = match choice.parse::<usize>() {
+            Ok(n) => (1..=self.buffers.len()).contains(&n).then(|| n - 1),
+            Err(_) => self.buffers.iter().position(|buffer| buffer.name().contains(choice)),
+        };
+        match found {
+            Some(i) => {
+                self.switch_buffer(i);
+                self.show_buffer_name();
+            }
+            None => self.set_status_msg(&format!("No buffer matches {}", choice)),
+        }
+        Ok(())
+    }
+
+    // Close the current buffer. Returns false, after a warning, while
+    // Ctrl-W still has to be pressed again to drop unsaved changes.
+    pub fn close_buffer(&mut self) -> bool {
+        if self.buffer().dirty && self.close_times > 0 {
+            self.set_status_msg(&format!(
+                "WARNING!!! Buffer has unsaved changes. Press Ctrl-W {} more times to close it.",
+                self.close_times
+            ));
+            self.close_times -= 1;
+            return false;
+        }
+        self.close_times = QUIT_TIMES;
+        let closed = self.current_buffer();
+        if self.buffers.len() == 1 {
+            // keep an empty buffer around
+            self.buffers[0] = Buffer::new(self.read_only);
+            for window in &mut self.windows {
+                window.view = View::default();
+            }
+            self.set_view(View::default());
+        } else {
+            let next = if closed + 1 < self.buffers.len() { closed + 1 } else { closed - 1 };
+            self.switch_buffer(next);
+            self.buffers.remove(closed);
+            self.buffer_removed(closed, next.min(closed));
+        }
+        self.search_hl = None;
+        self.show_buffer_name();
+        true
+    }
+
+    // " [2/3]" for buffer `i` when more than one buffer is open, for the
+    // status bar.
+    pub fn buffer_position(&self, i: usize) -> String {
+        if self.buffers.len() > 1 {
+            format!(" [{}/{}]", i + 1, self.buffers.len())
+        } else {
+            String::new()
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use crate::encoding::Encoding;
+use crate::goto::{parse_position, split_position};
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: kilo-rs [OPTIONS] [[+LINE[:COL]] FILE[:LINE[:COL]]]...
+
+Files are opened in buffers; `-` reads a buffer from standard input.
+
+Options:
+  -R, --readonly        open the files read-only
+      --tabstop N       expand tabs to N columns (default 8)
+      --encoding NAME   read the files as NAME (utf-8, latin-1, utf-16le
+                        or utf-16be) instead of detecting it
+      --line LINE[:COL] start at LINE of the next file, like +LINE
+      --config PATH     read syntax definitions from PATH instead of
+                        ~/.config/kilo-rs/syntax.conf
+  -h, --help            print this help and exit
+  -V, --version         print the version and exit";
+
+// A 1-based line and optional column to start at.
+pub type Position = (usize, Option<usize>);
+
+// A file to open, or `-` for standard input.
+pub struct FileArg {
+    pub name: String,
+    pub position: Option<Position>,
+}
+
+#[derive(Default)]
+pub struct Options {
+    pub files: Vec<FileArg>,
+    pub read_only: bool,
+    pub tab_stop: Option<usize>,
+    pub encoding: Option<Encoding>,
+    pub config: Option<PathBuf>,
+}
+
+pub enum Command {
+    Edit(Options),
+    Help,
+    Version,
+}
+
+// Parse the arguments after the program name. Errors are messages for
+// stderr, to be followed by the usage.
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    let mut position = None; // for the next file
+    let mut only_files = false; // after `--`
+    while let Some(arg) = args.next() {
+        if only_files || arg == "-" || !arg.starts_with('-') {
+            // like vi, a `+` not followed by a position is a file name
+            if let Some(pos) = arg.strip_prefix('+').filter(|_| !only_files).and_then(parse_position) {
+                position = Some(pos);
+                continue;
+            }
+            let (name, pos) = match position.take() {
+                Some(pos) => (arg.as_str(), Some(pos)),
+                None if arg == "-" => (arg.as_str(), None),
+                None => split_position(&arg),
+            };
+            options.files.push(FileArg { name: name.to_string(), position: pos });
+            continue;
+        }
+        // `--name=value` or `--name value`
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value.clone().or_else(|| args.next()).ok_or_else(|| format!("{} needs a value", name))
+        };
+        match name {
+            "--" => only_files = true,
+            "-R" | "--readonly" => options.read_only = true,
+            "--tabstop" => {
+                let value = value()?;
+                match value.parse() {
+                    Ok(n) if (1..=32).contains(&n) => options.tab_stop = Some(n),
+                    _ => return Err(format!("invalid tab stop: {}", value)),
+                }
+            }
+            "--encoding" => {
+                let value = value()?;
+                let encoding = Encoding::from_name(&value).ok_or_else(|| format!("unknown encoding: {}", value))?;
+                options.encoding = Some(encoding);
+            }
+            "--line" => {
+                let value = value()?;
+                position = Some(parse_position(&value).ok_or_else(|| format!("invalid line: {}", value))?);
+            }
+            "--config" => options.config = Some(PathBuf::from(value()?)),
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            _ => return Err(format!("unknown option: {}", arg)),
+        }
+    }
+    Ok(Command::Edit(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn files(args: &[&str]) -> Vec<(String, Option<Position>)> {
+        match parse(args) {
+            Ok(Command::Edit(options)) => {
+                options.files.into_iter().map(|file| (file.name, file.position)).collect()
+            }
+            _ => panic!("{:?} did not parse to files", args),
+        }
+    }
+
+    fn file(name: &str, position: Option<Position>) -> (String, Option<Position>) {
+        (name.to_string(), position)
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(files(&["+12", "a"]), [file("a", Some((12, None)))]);
+        assert_eq!(files(&["+12:5", "a", "b"]), [file("a", Some((12, Some(5)))), file("b", None)]);
+        assert_eq!(files(&["no/such/file:3:4"]), [file("no/such/file", Some((3, Some(4))))]);
+        assert_eq!(files(&["--line", "7", "a"]), [file("a", Some((7, None)))]);
+    }
+
+    #[test]
+    fn non_numeric_plus_is_a_file() {
+        assert_eq!(files(&["+foo"]), [file("+foo", None)]);
+        assert_eq!(files(&["+1:x", "a"]), [file("+1:x", None), file("a", None)]);
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        assert_eq!(
+            files(&["--", "-R", "+3", "--help"]),
+            [file("-R", None), file("+3", None), file("--help", None)]
+        );
+    }
+
+    #[test]
+    fn dash_is_stdin() {
+        assert_eq!(files(&["+2", "-", "-"]), [file("-", Some((2, None))), file("-", None)]);
+    }
+
+    #[test]
+    fn tab_stop() {
+        assert!(matches!(parse(&["--tabstop=4"]), Ok(Command::Edit(Options { tab_stop: Some(4), .. }))));
+        assert!(matches!(parse(&["--tabstop", "32"]), Ok(Command::Edit(Options { tab_stop: Some(32), .. }))));
+        assert_eq!(parse(&["--tabstop", "0"]).err().unwrap(), "invalid tab stop: 0");
+        assert_eq!(parse(&["--tabstop", "33"]).err().unwrap(), "invalid tab stop: 33");
+        assert_eq!(parse(&["--tabstop"]).err().unwrap(), "--tabstop needs a value");
+    }
+
+    #[test]
+    fn encoding() {
+        assert!(matches!(
+            parse(&["--encoding", "Latin1", "a"]),
+            Ok(Command::Edit(Options { encoding: Some(Encoding::Latin1), .. }))
+        ));
+        assert_eq!(parse(&["--encoding=ebcdic"]).err().unwrap(), "unknown encoding: ebcdic");
+    }
+
+    #[test]
+    fn unknown_options() {
+        assert_eq!(parse(&["-x"]).err().unwrap(), "unknown option: -x");
+        assert_eq!(parse(&["--nope=1"]).err().unwrap(), "unknown option: --nope=1");
+    }
+
+    #[test]
+    fn help_and_version() {
+        assert!(matches!(parse(&["a", "--help"]), Ok(Command::Help)));
+        assert!(matches!(parse(&["-V"]), Ok(Command::Version)));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::editor::Editor;
+
+use std::io::{self, Write};
+
+const KILL_RING_SIZE: usize = 16;
+
+// Recently cut or copied texts, newest last.
+pub struct KillRing {
+    entries: Vec<String>,
+    appending: bool, // consecutive line kills grow the newest entry
+}
+
+impl KillRing {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            appending: false,
+        }
+    }
+
+    fn push(&mut self, text: String) {
+        if self.entries.len() == KILL_RING_SIZE {
+            self.entries.remove(0);
+        }
+        self.entries.push(text);
+    }
+
+    fn append(&mut self, text: &str) {
+        match self.entries.last_mut() {
+            Some(last) if self.appending => last.push_str(text),
+            _ => self.push(text.to_string()),
+        }
+        self.appending = true;
+    }
+
+    pub fn end_append(&mut self) {
+        self.appending = false;
+    }
+
+    fn top(&self) -> Option<&str> {
+        self.entries.last().map(String::as_str)
+    }
+
+    // Make the previous entry the one pasted next.
+    fn rotate(&mut self) {
+        if let Some(last) = self.entries.pop() {
+            self.entries.insert(0, last);
+        }
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+impl Editor {
+    // Copy the selection, or the current line when nothing is selected.
+    pub fn copy(&mut self) {
+        let Some(text) = self.selected_text().or_else(|| self.current_line()) else {
+            return;
+        };
+        self.set_status_msg(&format!("Copied {} bytes", text.len()));
+        self.kill(text);
+    }
+
+    // Cut the selection, or the current line when nothing is selected.
+    pub fn cut(&mut self) {
+        if self.refuse_read_only() {
+            return;
+        }
+        if let Some(text) = self.selected_text() {
+            self.delete_selection();
+            self.kill(text);
+        } else if let Some(mut text) = self.current_line() {
+            let len = self.buffer().rows.get_line(self.cy).len();
+            if self.cy + 1 < self.buffer().rows.count() {
+                self.delete_range((self.cy, 0), (self.cy + 1, 0));
+            } else if self.cy > 0 {
+                // the last row goes with the line break before it
+                let prev = self.cy - 1;
+                let prev_len = self.buffer().rows.get_line(prev).len();
+                self.delete_range((prev, prev_len), (self.cy, len));
+                self.cx = 0;
+            } else {
+                // the only row stays, so its text is all that is cut
+                text.pop();
+                self.delete_range((self.cy, 0), (self.cy, len));
+            }
+            self.kill(text);
+        }
+    }
+
+    // Cut from the cursor to the end of the line, or the line break when
+    // already there. Repeated kills collect into one kill ring entry.
+    pub fn kill_line(&mut self) {
+        if self.refuse_read_only() {
+            return;
+        }
+        if self.cy >= self.buffer().rows.count() {
+            return;
+        }
+        let len = self.buffer().rows.get_line(self.cy).len();
+        let end = if self.cx < len {
+            (self.cy, len)
+        } else if self.cy + 1 < self.buffer().rows.count() {
+            (self.cy + 1, 0)
+        } else {
+            return;
+        };
+        let text = self.text_range((self.cy, self.cx), end);
+        self.delete_range((self.cy, self.cx), end);
+        self.kill_ring.append(&text);
+        if let Some(text) = self.kill_ring.top() {
+            self.send_to_clipboard(text);
+        }
+    }
+
+    // Paste the newest kill ring entry over the selection, if any.
+    pub fn paste(&mut self) {
+        let Some(text) = self.kill_ring.top().map(str::to_string) else {
+            self.set_status_msg("Kill ring is empty");
+            return;
+        };
+        self.delete_selection();
+        self.insert_text(&text);
+    }
+
+    pub fn rotate_kill_ring(&mut self) {
+        self.kill_ring.rotate();
+        if let Some(text) = self.kill_ring.top() {
+            let preview: String = text.chars().take(40).map(|c| if c == '\n' { '⏎' } else { c }).collect();
+            self.set_status_msg(&format!("Next paste: {}", preview));
+        }
+    }
+
+    pub fn toggle_osc52(&mut self) {
+        self.osc52 = !self.osc52;
+        self.set_status_msg(if self.osc52 {
+            "Copies go to the system clipboard"
+        } else {
+            "Copies stay in the editor"
+        });
+    }
+
+    fn current_line(&self) -> Option<String> {
+        (self.cy < self.buffer().rows.count()).then(|| format!("{}\n", self.buffer().rows.get_line(self.cy).chars))
+    }
+
+    fn kill(&mut self, text: String) {
+        self.send_to_clipboard(&text);
+        self.kill_ring.push(text);
+    }
+
+    // Hand the text to the terminal's clipboard with the OSC 52 escape,
+    // which also works over SSH.
+    fn send_to_clipboard(&self, text: &str) {
+        if !self.osc52 {
+            return;
+        }
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()));
+        let _ = stdout.flush();
+    }
+}
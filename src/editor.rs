@@ -1,12 +1,24 @@
 use nix::sys::termios::Termios;
 use anyhow::{Result, Context};
+use std::rc::Rc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::rope::RopeNode;
+use crate::buffer::Buffer;
+use crate::clipboard::KillRing;
+use crate::file_io::LineEnding;
+use crate::gutter::LineNumbers;
+use crate::input::QUIT_TIMES;
+use crate::mouse::MouseEvent;
+use crate::operations::TAB_STOP;
+use crate::syntax::{Highlight, Syntax};
+use crate::window::{Layout, Rect, View, Window};
 
 pub struct EditorRow {
     pub chars: String,
-    pub render: String
+    pub render: String,
+    pub hl: Vec<Highlight>, // highlight class of each char in `render`
+    pub hl_open_comment: bool,
+    pub line_ending: Option<LineEnding>, // set when it differs from the file's
 }
 
 pub struct Editor {
@@ -14,40 +26,73 @@ pub struct Editor {
     pub cy: usize,
     pub rx: usize,
     pub row_off: usize,
+    pub wrap_off: usize, // visual lines of `row_off` scrolled off with soft wrap
     pub col_off: usize,
     pub screenrows: usize,
     pub screencols: usize,
-    pub rows: RopeNode<EditorRow>,
-    pub dirty: bool,
-    pub file_name: String,
+    pub quit_times: usize,
+    pub close_times: usize,
+    pub buffers: Vec<Buffer>,
+    pub windows: Vec<Window>, // the focused one's view is in the fields above
+    pub layout: Layout,
+    pub current_window: usize,
+    pub separators: Vec<Rect>, // columns between windows side by side
+    pub term_rows: usize, // terminal rows above the message bar
+    pub term_cols: usize,
+    pub syntax_db: Vec<Rc<Syntax>>,
     pub status_msg: String,
+    pub search_hl: Option<(usize, usize, usize)>, // row and char range of the current match
+    pub kill_ring: KillRing,
+    pub osc52: bool, // mirror copies to the terminal clipboard
+    pub mouse: Option<MouseEvent>, // last event read as MOUSE
+    pub line_numbers: LineNumbers,
+    pub soft_wrap: bool,
+    pub tab_stop: usize,
+    pub read_only: bool, // for new buffers, set by -R
     pub status_msg_time: SystemTime,
     pub ori_termios: Termios,
-    pub termios: Termios,
 }
 
 impl Editor {
     pub fn new() -> Result<Self> {
-        let (ori_termios, termios) = Self::enable_raw_mode()
+        let ori_termios = Self::enable_raw_mode()
             .context("Failed to enable raw mode")?;
         let (screencols, screenrows) = Self::get_window_size()
             .context("Failed to get window size")?;
-        Ok(Self {
+        Self::install_resize_handler()?;
+        let mut editor = Self {
             cx: 0,
             cy: 0,
             rx: 0,
             row_off: 0,
+            wrap_off: 0,
             col_off: 0,
-            screenrows: screenrows - 2, // leave 2 line for status and msg bar
-            screencols,
-            rows: RopeNode::default(),
-            dirty: false,
-            file_name: String::new(),
+            screenrows: 0,
+            screencols: 0,
+            quit_times: QUIT_TIMES,
+            close_times: QUIT_TIMES,
+            buffers: vec![Buffer::new(false)],
+            windows: vec![Window::new(0, View::default())],
+            layout: Layout::Window(0),
+            current_window: 0,
+            separators: Vec::new(),
+            term_rows: 0,
+            term_cols: 0,
+            syntax_db: Syntax::builtin().into_iter().map(Rc::new).collect(),
             status_msg: String::new(),
+            search_hl: None,
+            kill_ring: KillRing::new(),
+            osc52: true,
+            mouse: None,
+            line_numbers: LineNumbers::Off,
+            soft_wrap: false,
+            tab_stop: TAB_STOP,
+            read_only: false,
             status_msg_time: UNIX_EPOCH,
             ori_termios,
-            termios
-        })
+        };
+        editor.set_window_size(screencols, screenrows);
+        Ok(editor)
     }
 }
 
@@ -0,0 +1,196 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::mem;
+use anyhow::{anyhow, Result};
+
+// Bytes that are not valid UTF-8 are kept in the buffer as chars in this
+// private use range, so they can be shown as `\xNN` and written back as is.
+// Real chars from the range never enter a buffer as themselves: they are
+// kept as the raw bytes of their UTF-8 encoding, which write back the same.
+const RAW_BYTE_BASE: u32 = 0x10FF00;
+
+pub fn raw_byte(c: char) -> Option<u8> {
+    let c = c as u32;
+    if (RAW_BYTE_BASE + 0x80..=RAW_BYTE_BASE + 0xFF).contains(&c) {
+        Some((c - RAW_BYTE_BASE) as u8)
+    } else {
+        None
+    }
+}
+
+fn raw_byte_char(b: u8) -> char {
+    char::from_u32(RAW_BYTE_BASE + b as u32).unwrap()
+}
+
+// `text` with any chars from the raw byte range spelled as raw bytes, for
+// text coming into a buffer.
+pub fn escape_raw_range(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(|c| raw_byte(c).is_some()) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len() * 4);
+    for c in text.chars() {
+        match raw_byte(c) {
+            Some(_) => escaped.extend(c.encode_utf8(&mut [0; 4]).bytes().map(raw_byte_char)),
+            None => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Encoding::Utf8 => write!(f, "utf-8"),
+            Encoding::Latin1 => write!(f, "latin-1"),
+            Encoding::Utf16Le => write!(f, "utf-16le"),
+            Encoding::Utf16Be => write!(f, "utf-16be"),
+        }
+    }
+}
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            _ => None,
+        }
+    }
+
+    // UTF-16 is recognised by its BOM; anything else is read as UTF-8.
+    pub fn detect(bytes: &[u8]) -> Self {
+        match bytes {
+            [0xFF, 0xFE, ..] => Encoding::Utf16Le,
+            [0xFE, 0xFF, ..] => Encoding::Utf16Be,
+            _ => Encoding::Utf8,
+        }
+    }
+
+    // A leading BOM is kept as U+FEFF. Invalid UTF-8 bytes survive as raw
+    // byte chars; unpaired UTF-16 surrogates become U+FFFD.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Encoding::Utf8 => {
+                let mut text = String::with_capacity(bytes.len());
+                for chunk in bytes.utf8_chunks() {
+                    text.push_str(&escape_raw_range(chunk.valid()));
+                    text.extend(chunk.invalid().iter().map(|&b| raw_byte_char(b)));
+                }
+                text
+            }
+            Encoding::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units = bytes.chunks(2).map(|pair| match (self, pair) {
+                    (Encoding::Utf16Le, [lo, hi]) => u16::from_le_bytes([*lo, *hi]),
+                    (_, [hi, lo]) => u16::from_be_bytes([*hi, *lo]),
+                    _ => 0xFFFD, // odd trailing byte
+                });
+                let text: String = char::decode_utf16(units)
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect();
+                escape_raw_range(&text).into_owned()
+            }
+        }
+    }
+
+    pub fn encode(self, text: &str, out: &mut Vec<u8>) -> Result<()> {
+        match self {
+            Encoding::Utf8 => {
+                for c in text.chars() {
+                    match raw_byte(c) {
+                        Some(b) => out.push(b),
+                        None => out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                    }
+                }
+            }
+            Encoding::Latin1 => {
+                for c in self.join_raw_bytes(text)?.chars() {
+                    let b = u8::try_from(c)
+                        .map_err(|_| anyhow!("'{}' can't be encoded as {}", c, self))?;
+                    out.push(b);
+                }
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                for unit in self.join_raw_bytes(text)?.encode_utf16() {
+                    out.extend_from_slice(&match self {
+                        Encoding::Utf16Le => unit.to_le_bytes(),
+                        _ => unit.to_be_bytes(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Undo `escape_raw_range` for encodings other than UTF-8: raw bytes
+    // that spell a char become that char again, other raw bytes can't be
+    // encoded.
+    fn join_raw_bytes(self, text: &str) -> Result<Cow<'_, str>> {
+        if !text.chars().any(|c| raw_byte(c).is_some()) {
+            return Ok(Cow::Borrowed(text));
+        }
+        let mut joined = String::with_capacity(text.len());
+        let mut bytes = Vec::new();
+        for c in text.chars().map(Some).chain([None]) {
+            if let Some(b) = c.and_then(raw_byte) {
+                bytes.push(b);
+                continue;
+            }
+            match String::from_utf8(mem::take(&mut bytes)) {
+                Ok(s) => joined.push_str(&s),
+                Err(e) => {
+                    let b = e.as_bytes()[e.utf8_error().valid_up_to()];
+                    return Err(anyhow!("byte \\x{:02X} can't be encoded as {}", b, self));
+                }
+            }
+            joined.extend(c);
+        }
+        Ok(Cow::Owned(joined))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(encoding: Encoding, bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        encoding.encode(&encoding.decode(bytes), &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn raw_byte_range_chars_round_trip() {
+        let text = "a\u{10FF80}b\u{10FFFD}c\u{10FFFF}";
+        assert_eq!(round_trip(Encoding::Utf8, text.as_bytes()), text.as_bytes());
+
+        let utf16: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(round_trip(Encoding::Utf16Le, &utf16), utf16);
+    }
+
+    #[test]
+    fn invalid_utf8_round_trips_next_to_raw_byte_range_chars() {
+        let mut bytes = b"x\xff\xc3(".to_vec();
+        bytes.extend_from_slice("\u{10FF81}".as_bytes());
+        bytes.push(0x80);
+        assert_eq!(round_trip(Encoding::Utf8, &bytes), bytes);
+    }
+
+    #[test]
+    fn raw_bytes_are_refused_by_other_encodings() {
+        let text = Encoding::Utf8.decode(b"\xff");
+        assert!(Encoding::Utf16Le.encode(&text, &mut Vec::new()).is_err());
+        assert!(Encoding::Latin1.encode(&text, &mut Vec::new()).is_err());
+    }
+}
@@ -1,60 +1,422 @@
 use crate::editor::*;
+use crate::encoding::Encoding;
 use crate::rope::*;
+use crate::undo::UndoStack;
 
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::fmt;
 use std::io::BufWriter;
-use std::io::{self, BufRead, Write};
-use anyhow::{anyhow, Context, Result};
+use std::io::{self, Write};
+use std::os::unix::fs::{fchown, MetadataExt};
+use std::path::Path;
+use std::process;
+use nix::unistd::{access, AccessFlags};
+use anyhow::{Context, Result};
 
-impl Editor {
-    pub fn open_file(&mut self, file_name: &str) -> Result<()>  {
-        let file = File::open(file_name).context("Failed to open file")?;
-        let reader = io::BufReader::new(file);
+const BOM: char = '\u{feff}';
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LineEnding::Lf => write!(f, "LF"),
+            LineEnding::CrLf => write!(f, "CRLF"),
+        }
+    }
+}
+
+// How the file on disk is laid out, so it can be written back the same way.
+#[derive(Clone, Copy)]
+pub struct FileFormat {
+    pub encoding: Encoding,
+    pub line_ending: LineEnding,
+    pub final_newline: bool,
+    pub bom: bool,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            final_newline: true,
+            bom: false,
+        }
+    }
+}
+
+impl FileFormat {
+    // Split `text` into lines with their line endings and detect its format,
+    // taking the more common line ending. Also returns whether the file
+    // mixes LF and CRLF line endings.
+    fn split_lines(text: &str, encoding: Encoding) -> (Vec<(&str, LineEnding)>, FileFormat, bool) {
+        let (text, bom) = match text.strip_prefix(BOM) {
+            Some(text) => (text, true),
+            None => (text, false),
+        };
+        let final_newline = text.is_empty() || text.ends_with('\n');
+        let mut lines: Vec<&str> = text.split('\n').collect();
+        if final_newline {
+            lines.pop(); // nothing after the last newline
+        }
+
+        let (mut lf, mut crlf) = (0, 0);
+        let last = lines.len().saturating_sub(1);
+        let mut endings = Vec::with_capacity(lines.len());
+        for (i, line) in lines.iter_mut().enumerate() {
+            if i == last && !final_newline {
+                break; // the last line has no line ending
+            }
+            match line.strip_suffix('\r') {
+                Some(stripped) => {
+                    *line = stripped;
+                    crlf += 1;
+                    endings.push(LineEnding::CrLf);
+                }
+                None => {
+                    lf += 1;
+                    endings.push(LineEnding::Lf);
+                }
+            }
+        }
+
+        let line_ending = if crlf > lf { LineEnding::CrLf } else { LineEnding::Lf };
+        endings.resize(lines.len(), line_ending);
+        let format = FileFormat {
+            encoding,
+            line_ending,
+            final_newline,
+            bom,
+        };
+        (lines.into_iter().zip(endings).collect(), format, lf > 0 && crlf > 0)
+    }
+
+    // Split `text` into rows, each keeping its line ending when it differs
+    // from the file's. Also returns the format and whether it is mixed.
+    fn read_rows(text: &str, encoding: Encoding) -> (RopeNode<EditorRow>, FileFormat, bool) {
+        let (lines, format, mixed) = Self::split_lines(text, encoding);
         let mut builder = RopeBuilder::<EditorRow>::new();
-        for line in reader.lines() {
-            let mut line = line?;
-            while line.ends_with(['\n', '\r']) {
-                line.pop();
+        for (line, line_ending) in lines {
+            let mut row = EditorRow::new(line);
+            if line_ending != format.line_ending {
+                row.line_ending = Some(line_ending);
             }
-            builder.insert(EditorRow::new(&line));
+            builder.insert(row);
+        }
+        (builder.build().unwrap_or_default(), format, mixed)
+    }
+
+    // Encode `rows` laid out in this format to `writer`. Returns the number
+    // of bytes written.
+    fn write_rows(self, rows: &RopeNode<EditorRow>, writer: &mut impl Write) -> Result<usize> {
+        let mut buf = Vec::new();
+        let encoding = self.encoding;
+        if self.bom {
+            // written even when there are no rows
+            encoding.encode(&BOM.to_string(), &mut buf)?;
+            writer.write_all(&buf)?;
+        }
+        let mut bytes = buf.len();
+        buf.clear();
+        let count = rows.count();
+        for (i, row) in rows.lines().enumerate() {
+            encoding.encode(&row.chars, &mut buf)
+                .with_context(|| format!("line {}", i + 1))?;
+            if i + 1 < count || self.final_newline {
+                let eol = row.line_ending.unwrap_or(self.line_ending);
+                encoding.encode(eol.as_str(), &mut buf)?;
+            }
+            writer.write_all(&buf)?;
+            bytes += buf.len();
+            buf.clear();
+        }
+        Ok(bytes)
+    }
+}
+
+impl Editor {
+    // Without an explicit encoding, UTF-16 is detected from its BOM and
+    // everything else is read as UTF-8, keeping any invalid bytes.
+    pub fn open_file(&mut self, file_name: &str, encoding: Option<Encoding>) -> Result<()>  {
+        let bytes = fs::read(file_name).context("Failed to open file")?;
+        self.buffer_mut().file_name = file_name.to_string();
+        self.load_bytes(&bytes, encoding);
+        // -R, or a file we can't write to
+        let read_only = self.read_only || access(file_name, AccessFlags::W_OK).is_err();
+        self.buffer_mut().read_only = read_only;
+        Ok(())
+    }
+
+    // Replace the buffer's text with `bytes` decoded.
+    pub fn load_bytes(&mut self, bytes: &[u8], encoding: Option<Encoding>) {
+        let encoding = encoding.unwrap_or_else(|| Encoding::detect(bytes));
+        let text = encoding.decode(bytes);
+        let (rows, format, mixed) = FileFormat::read_rows(&text, encoding);
+        let buffer = self.buffer_mut();
+        buffer.rows = rows;
+        buffer.undo = UndoStack::new();
+        buffer.format = format;
+        self.select_syntax();
+        if mixed {
+            self.set_status_msg(&format!(
+                "File has mixed line endings, kept as they are; Alt-L converts them to {}",
+                format.line_ending
+            ));
+        }
+    }
+
+    // Only re-reads the file, so read-only buffers can be reopened too.
+    pub fn reopen_with_encoding(&mut self) -> Result<()> {
+        if self.buffer().file_name.is_empty() {
+            self.set_status_msg("No file to reopen");
+            return Ok(());
+        }
+        if self.buffer().dirty {
+            self.set_status_msg("Unsaved changes, save before reopening");
+            return Ok(());
         }
-        self.rows = builder.build().unwrap();
-        self.file_name = file_name.to_string();
+        let Some(name) = self.prompt("Reopen with encoding (utf-8, latin-1, utf-16le, utf-16be)", None)? else {
+            return Ok(());
+        };
+        let Some(encoding) = Encoding::from_name(name.trim()) else {
+            self.set_status_msg(&format!("Unknown encoding: {}", name));
+            return Ok(());
+        };
+        let file_name = self.buffer().file_name.clone();
+        let read_only = self.buffer().read_only;
+        if let Err(e) = self.open_file(&file_name, Some(encoding)) {
+            self.set_status_msg(&format!("{:#}", e));
+            return Ok(());
+        }
+        self.buffer_mut().read_only = read_only;
+        self.cy = self.cy.min(self.buffer().rows.count());
+        self.cx = 0;
+        self.set_status_msg(&format!("Reopened as {}", encoding));
         Ok(())
     }
 
-    pub fn rows_to_string(&self) -> String {
-        let mut buf = String::new();
-        for row in self.rows.lines() {
-            buf.push_str(&row.chars);
-            buf.push_str("\n");
-        }        
-        buf
+    // Convert a file with mixed line endings to its most common one, or
+    // switch between LF and CRLF.
+    pub fn toggle_line_ending(&mut self) {
+        if self.refuse_read_only() {
+            return;
+        }
+        let buffer = self.buffer_mut();
+        let mut mixed = false;
+        for i in 0..buffer.rows.count() {
+            mixed |= buffer.rows.get_line_mut(i).line_ending.take().is_some();
+        }
+        let format = &mut buffer.format;
+        if !mixed {
+            format.line_ending = match format.line_ending {
+                LineEnding::Lf => LineEnding::CrLf,
+                LineEnding::CrLf => LineEnding::Lf,
+            };
+        }
+        let msg = format!("Line endings set to {}", format.line_ending);
+        self.set_status_msg(&msg);
+        self.format_changed();
     }
 
+    pub fn toggle_final_newline(&mut self) {
+        if self.refuse_read_only() {
+            return;
+        }
+        let format = &mut self.buffer_mut().format;
+        format.final_newline = !format.final_newline;
+        let final_newline = format.final_newline;
+        self.set_status_msg(if final_newline {
+            "File will end with a newline"
+        } else {
+            "File will not end with a newline"
+        });
+        self.format_changed();
+    }
+
+    pub fn toggle_bom(&mut self) {
+        if self.refuse_read_only() {
+            return;
+        }
+        let format = &mut self.buffer_mut().format;
+        format.bom = !format.bom;
+        let bom = format.bom;
+        self.set_status_msg(if bom {
+            "File will start with a UTF-8 BOM"
+        } else {
+            "File will not start with a BOM"
+        });
+        self.format_changed();
+    }
+
+    fn format_changed(&mut self) {
+        let buffer = self.buffer_mut();
+        buffer.dirty = true;
+        buffer.undo.forget_saved();
+    }
+
+    // Write failures are reported in the message bar; only errors reading
+    // the "Save as" prompt are returned.
     pub fn save_file(&mut self) -> Result<()>{
-        if self.file_name.is_empty() {
-            if let Some(file_name) = self.prompt("Save as")? {
-                if !file_name.is_empty() {
-                    self.file_name = file_name;
-                } else {
+        if self.refuse_read_only() {
+            return Ok(());
+        }
+        if self.buffer().file_name.is_empty() {
+            match self.prompt("Save as", None)? {
+                Some(file_name) if !file_name.is_empty() => {
+                    self.buffer_mut().file_name = file_name;
+                    self.select_syntax();
+                }
+                Some(_) => {
                     self.set_status_msg("Empty file name");
+                    return Ok(());
+                }
+                None => {
+                    self.set_status_msg("Save aborted");
+                    return Ok(());
                 }
-            } else {
-                self.set_status_msg("Save aborted");
             }
         }
+        match self.write_file(Path::new(&self.buffer().file_name)) {
+            Ok(bytes) => {
+                let buffer = self.buffer_mut();
+                buffer.dirty = false;
+                buffer.undo.mark_saved();
+                self.set_status_msg(&format!("{} bytes written to disk", bytes));
+            }
+            Err(e) => self.set_status_msg(&format!("Can't save! {:#}", e)),
+        }
+        Ok(())
+    }
+
+    // Write the buffer to a temporary file next to `path` and rename it over
+    // the target, so a failed write never leaves a truncated file behind.
+    fn write_file(&self, path: &Path) -> Result<usize> {
+        // write through symlinks to the real file
+        let target = match fs::canonicalize(path) {
+            Ok(target) => target,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => path.to_path_buf(),
+            Err(e) => return Err(e).context("Failed to resolve path"),
+        };
+        let original = match fs::metadata(&target) {
+            Ok(metadata) => Some(metadata),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e).context("Failed to read file metadata"),
+        };
+
+        let dir = match target.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = target.file_name().context("Not a file name")?;
+        let tmp_path = dir.join(format!(
+            ".{}.{}.tmp",
+            name.to_string_lossy(),
+            process::id()
+        ));
+
+        let result = self
+            .write_tmp_file(&tmp_path, original.as_ref())
+            .and_then(|bytes| {
+                fs::rename(&tmp_path, &target).context("Failed to replace file")?;
+                Ok(bytes)
+            });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        } else if let Ok(dir) = File::open(dir) {
+            // make the rename itself durable
+            let _ = dir.sync_all();
+        }
+        result
+    }
+
+    fn write_tmp_file(&self, tmp_path: &Path, original: Option<&Metadata>) -> Result<usize> {
         let file = OpenOptions::new()
             .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&self.file_name)
-            .context("Failed to open file for writing")?;
+            .create_new(true)
+            .open(tmp_path)
+            .context("Failed to create temporary file")?;
+        if let Some(metadata) = original {
+            // only root can give files away; keep our ownership otherwise
+            let _ = fchown(&file, Some(metadata.uid()), Some(metadata.gid()));
+            file.set_permissions(metadata.permissions())
+                .context("Failed to copy file permissions")?;
+        }
+
         let mut writer = BufWriter::new(file);
-        for row in self.rows.lines() {
-            writeln!(writer, "{}", row.chars)?; // writes a line with a newline
+        let buffer = self.buffer();
+        let bytes = buffer.format.write_rows(&buffer.rows, &mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all().context("Failed to sync file")?;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8]) -> Vec<u8> {
+        let encoding = Encoding::detect(bytes);
+        let (rows, format, _) = FileFormat::read_rows(&encoding.decode(bytes), encoding);
+        let mut out = Vec::new();
+        format.write_rows(&rows, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn files_round_trip() {
+        for bytes in [
+            &b"a\nb\n"[..],
+            b"a\r\nb\r\n",
+            b"a\r\nb\nc\r\n",
+            b"a\nb",
+            b"a\r\nb",
+            b"",
+            b"\n",
+            b"\r\n",
+            b"\xEF\xBB\xBFa\nb\n",
+            b"\xEF\xBB\xBF",
+            b"\xFF\xFEa\0\r\0\n\0",
+            b"a\r",
+            b"a\nb\r",
+        ] {
+            assert_eq!(round_trip(bytes), bytes, "{:?}", String::from_utf8_lossy(bytes));
         }
-        self.dirty = false;
-        Ok(())
+    }
+
+    #[test]
+    fn split_lines_detects_the_format() {
+        let (lines, format, mixed) = FileFormat::split_lines("a\r\nb\r\nc\n", Encoding::Utf8);
+        assert_eq!(lines, [("a", LineEnding::CrLf), ("b", LineEnding::CrLf), ("c", LineEnding::Lf)]);
+        assert!(format.line_ending == LineEnding::CrLf && format.final_newline && !format.bom);
+        assert!(mixed);
+
+        let (lines, format, mixed) = FileFormat::split_lines("\u{feff}a\r\nb", Encoding::Utf8);
+        assert_eq!(lines, [("a", LineEnding::CrLf), ("b", LineEnding::CrLf)]);
+        assert!(format.line_ending == LineEnding::CrLf && !format.final_newline && format.bom);
+        assert!(!mixed);
+
+        let (lines, format, _) = FileFormat::split_lines("", Encoding::Utf8);
+        assert!(lines.is_empty());
+        assert!(format.final_newline);
+
+        // a lone `\r` at the end is text, not a line ending
+        let (lines, format, _) = FileFormat::split_lines("a\r", Encoding::Utf8);
+        assert_eq!(lines, [("a\r", LineEnding::Lf)]);
+        assert!(!format.final_newline);
     }
 }
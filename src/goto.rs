@@ -0,0 +1,76 @@
+use crate::editor::Editor;
+
+use std::path::Path;
+use anyhow::Result;
+
+// A 1-based "line" or "line:col", as typed at the prompt or given after `+`
+// on the command line.
+pub fn parse_position(s: &str) -> Option<(usize, Option<usize>)> {
+    let (line, col) = match s.split_once(':') {
+        Some((line, col)) => (line, Some(col.parse().ok()?)),
+        None => (s, None),
+    };
+    Some((line.parse().ok()?, col))
+}
+
+// Split a trailing ":line[:col]" off a file name, as in compiler messages,
+// unless a file by the whole name exists.
+pub fn split_position(arg: &str) -> (&str, Option<(usize, Option<usize>)>) {
+    if !Path::new(arg).exists() {
+        let trimmed = arg.trim_end_matches(':');
+        for (i, _) in trimmed.match_indices(':').filter(|&(i, _)| i > 0) {
+            if let Some(position) = parse_position(&trimmed[i + 1..]) {
+                return (&trimmed[..i], Some(position));
+            }
+        }
+    }
+    (arg, None)
+}
+
+impl Editor {
+    pub fn go_to_line_prompt(&mut self) -> Result<()> {
+        let Some(input) = self.prompt("Go to line[:col]", None)? else {
+            return Ok(());
+        };
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(());
+        }
+        match parse_position(input) {
+            Some((line, col)) => self.go_to(line, col),
+            None => self.set_status_msg(&format!("Invalid line: {}", input)),
+        }
+        Ok(())
+    }
+
+    // Move the cursor to 1-based `line` and `col`, clamped to the buffer,
+    // and center it in the window.
+    pub fn go_to(&mut self, line: usize, col: Option<usize>) {
+        let count = self.buffer().rows.count();
+        self.cy = line.saturating_sub(1).min(count.saturating_sub(1));
+        self.cx = 0;
+        if let (Some(col), true) = (col, self.cy < count) {
+            let row = self.buffer().rows.get_line(self.cy);
+            self.cx = row.grapheme_start(col.saturating_sub(1).min(row.len()));
+        }
+        self.center_cursor();
+    }
+
+    // Scroll so the cursor's line is in the middle of the window.
+    fn center_cursor(&mut self) {
+        self.update_rx();
+        if !self.soft_wrap {
+            self.row_off = self.cy.saturating_sub(self.screenrows / 2);
+            return;
+        }
+        // walk up half a window of visual lines
+        let (mut row, mut off) = (self.cy, self.cursor_visual_pos().0);
+        let mut remaining = self.screenrows / 2;
+        while remaining > off && row > 0 {
+            remaining -= off + 1;
+            row -= 1;
+            off = self.row_wrap_starts(row).len() - 1;
+        }
+        (self.row_off, self.wrap_off) = (row, off.saturating_sub(remaining));
+    }
+}
@@ -0,0 +1,64 @@
+use crate::editor::Editor;
+use crate::output::NORMAL_COLOR_CMD;
+use crate::window::Pane;
+
+const GUTTER_COLOR_CMD: &str = "\x1b[90m";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    Relative, // distance from the cursor row, which shows its own number
+}
+
+impl Editor {
+    pub fn toggle_line_numbers(&mut self) {
+        self.line_numbers = match self.line_numbers {
+            LineNumbers::Off => LineNumbers::Absolute,
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Off,
+        };
+        self.set_status_msg(match self.line_numbers {
+            LineNumbers::Off => "Line numbers off",
+            LineNumbers::Absolute => "Line numbers on",
+            LineNumbers::Relative => "Relative line numbers on",
+        });
+    }
+
+    pub fn gutter_width(&self) -> usize {
+        self.current_pane().gutter_width()
+    }
+}
+
+impl Pane<'_> {
+    // Columns taken by the gutter left of the text, including the space
+    // separating it from the text. Never the whole window.
+    pub fn gutter_width(&self) -> usize {
+        let width = match self.editor.line_numbers {
+            LineNumbers::Off => 0,
+            _ => self.buffer.rows.count().max(1).ilog10() as usize + 2,
+        };
+        if width < self.screencols { width } else { 0 }
+    }
+
+    // Screen columns left for the text.
+    pub fn text_cols(&self) -> usize {
+        self.screencols - self.gutter_width()
+    }
+
+    // The gutter cells for file row `row`, or for a filler line past the end
+    // of the file. Further columns, such as markers, go here too.
+    pub fn draw_gutter(&self, row: Option<usize>) -> String {
+        let width = self.gutter_width();
+        if width == 0 {
+            return String::new();
+        }
+        let cy = self.view.cy;
+        let number = match (self.editor.line_numbers, row) {
+            (_, None) => String::new(),
+            (LineNumbers::Relative, Some(row)) if row != cy => row.abs_diff(cy).to_string(),
+            (_, Some(row)) => (row + 1).to_string(),
+        };
+        format!("{}{:>w$} {}", GUTTER_COLOR_CMD, number, NORMAL_COLOR_CMD, w = width - 1)
+    }
+}
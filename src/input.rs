@@ -1,30 +1,97 @@
 use crate::editor::Editor;
 
-use std::process::exit;
-use anyhow::{Result, Context};
-
-macro_rules! ctrl_key {
-    ($k:expr) => {($k as u8 & 0x1f) as i32};
-}
+use anyhow::Result;
 
 const fn ctrl_key(c: char) -> i32 {
     (c as u8 & 0x1f) as i32
 }
 
+const fn alt_key(c: char) -> i32 {
+    ALT | c as i32
+}
+
 pub const BACKSPACE: i32 = 127;
-pub const ARROW_LEFT: i32 = 1000;
-pub const ARROW_RIGHT: i32 = 1001;
-pub const ARROW_UP: i32 = 1002;
-pub const ARROW_DOWN: i32 = 1003;
-pub const DEL_KEY: i32 = 1004;
-pub const HOME_KEY: i32 = 1005;
-pub const END_KEY: i32 = 1006;
-pub const PAGE_UP: i32 = 1007;
-pub const PAGE_DOWN: i32 = 1008;
+// special keys are numbered past the last unicode char, so any typed char
+// can be a key code too
+const KEY_BASE: i32 = 0x110000;
+pub const ARROW_LEFT: i32 = KEY_BASE;
+pub const ARROW_RIGHT: i32 = KEY_BASE + 1;
+pub const ARROW_UP: i32 = KEY_BASE + 2;
+pub const ARROW_DOWN: i32 = KEY_BASE + 3;
+pub const DEL_KEY: i32 = KEY_BASE + 4;
+pub const HOME_KEY: i32 = KEY_BASE + 5;
+pub const END_KEY: i32 = KEY_BASE + 6;
+pub const PAGE_UP: i32 = KEY_BASE + 7;
+pub const PAGE_DOWN: i32 = KEY_BASE + 8;
+pub const BACKTAB: i32 = KEY_BASE + 9;
+pub const CTRL_SPACE: i32 = KEY_BASE + 10;
+pub const PASTE_START: i32 = KEY_BASE + 11;
+pub const MOUSE: i32 = KEY_BASE + 12;
+
+// modifier bits or-ed into a key code
+pub const SHIFT: i32 = 1 << 22;
+pub const ALT: i32 = 1 << 23;
+pub const CTRL: i32 = 1 << 24;
+
+pub const CTRL_C: i32 = ctrl_key('c');
+pub const CTRL_K: i32 = ctrl_key('k');
+pub const CTRL_LEFT: i32 = CTRL | ARROW_LEFT;
+pub const CTRL_RIGHT: i32 = CTRL | ARROW_RIGHT;
+pub const CTRL_DEL: i32 = CTRL | DEL_KEY;
+pub const ALT_UP: i32 = ALT | ARROW_UP;
+pub const ALT_DOWN: i32 = ALT | ARROW_DOWN;
+pub const ALT_BACKSPACE: i32 = ALT | BACKSPACE;
+pub const CTRL_BACKSPACE: i32 = CTRL | BACKSPACE;
 
+pub const CTRL_B: i32 = ctrl_key('b');
+pub const CTRL_O: i32 = ctrl_key('o');
 pub const CTRL_Q: i32 = ctrl_key('q');
+pub const CTRL_W: i32 = ctrl_key('w');
+pub const CTRL_R: i32 = ctrl_key('r');
 pub const CTRL_S: i32 = ctrl_key('s');
+pub const CTRL_U: i32 = ctrl_key('u');
+pub const CTRL_V: i32 = ctrl_key('v');
+pub const CTRL_X: i32 = ctrl_key('x');
+pub const CTRL_F: i32 = ctrl_key('f');
+pub const CTRL_G: i32 = ctrl_key('g');
 pub const CTRL_H: i32 = ctrl_key('h');
+pub const TAB: i32 = ctrl_key('i');
+pub const CTRL_Y: i32 = ctrl_key('y');
+pub const CTRL_Z: i32 = ctrl_key('z');
+
+pub const ALT_COMMA: i32 = alt_key(',');
+pub const ALT_PERIOD: i32 = alt_key('.');
+pub const ALT_EQUALS: i32 = alt_key('=');
+pub const ALT_MINUS: i32 = alt_key('-');
+pub const ALT_B: i32 = alt_key('b');
+pub const ALT_C: i32 = alt_key('c');
+pub const ALT_E: i32 = alt_key('e');
+pub const ALT_G: i32 = alt_key('g');
+pub const ALT_L: i32 = alt_key('l');
+pub const ALT_N: i32 = alt_key('n');
+pub const ALT_O: i32 = alt_key('o');
+pub const ALT_Q: i32 = alt_key('q');
+pub const ALT_R: i32 = alt_key('r');
+pub const ALT_S: i32 = alt_key('s');
+pub const ALT_U: i32 = alt_key('u');
+pub const ALT_V: i32 = alt_key('v');
+pub const ALT_W: i32 = alt_key('w');
+pub const ALT_Y: i32 = alt_key('y');
+pub const ALT_SHIFT_U: i32 = alt_key('U');
+
+pub fn is_movement_key(key: i32) -> bool {
+    matches!(
+        key,
+        ARROW_UP | ARROW_DOWN | ARROW_LEFT | ARROW_RIGHT | PAGE_UP | PAGE_DOWN | HOME_KEY | END_KEY
+            | CTRL_LEFT | CTRL_RIGHT | ALT_UP | ALT_DOWN
+    )
+}
+
+// called after every key press in `prompt` with the current input and key
+pub type PromptCallback<'a> = &'a mut dyn FnMut(&mut Editor, &mut String, i32);
+
+// extra Ctrl-Q presses needed to quit with unsaved changes
+pub const QUIT_TIMES: usize = 3;
 
 pub const NEWLINE: i32 = '\r' as i32;
 pub const ESCAPE: i32 = '\x1b' as i32;
@@ -32,123 +99,298 @@ pub const ESCAPE: i32 = '\x1b' as i32;
 impl Editor {
     pub fn move_cursor(&mut self, key: i32) {
         match key {
-            ARROW_UP => {
-                if self.cy > 0 {
-                    self.cy -= 1;
-                }
+            ARROW_UP | ARROW_DOWN if self.soft_wrap => {
+                self.move_visual_line(key == ARROW_DOWN);
             }
-            ARROW_DOWN => {
-                if self.cy < self.rows.count() - 1 {
-                    self.cy += 1;
-                }
+            ARROW_UP if self.cy > 0 => {
+                self.cy -= 1;
+            }
+            ARROW_DOWN if self.cy + 1 < self.buffer().rows.count() => {
+                self.cy += 1;
             }
             ARROW_LEFT => {
-                if self.cx > 0 {
-                    self.cx -= 1;
+                if self.cx > 0 && self.cy < self.buffer().rows.count() {
+                    self.cx = self.buffer().rows.get_line(self.cy).prev_grapheme(self.cx);
                 } else if self.cy > 0{
                     self.cy -= 1;
-                    self.cx = self.rows.get_line(self.cy).len();
+                    self.cx = self.buffer().rows.get_line(self.cy).len();
                 }
             }
-            ARROW_RIGHT => {
-                if self.cx < self.rows.get_line(self.cy).len() {
-                    self.cx += 1;
-                } else if self.cy < self.rows.count() - 1{
+            ARROW_RIGHT if self.cy < self.buffer().rows.count() => {
+                let row = self.buffer().rows.get_line(self.cy);
+                if self.cx < row.len() {
+                    self.cx = row.next_grapheme(self.cx);
+                } else if self.cy < self.buffer().rows.count() - 1{
                     self.cy += 1;
                     self.cx = 0;
                 }
             }
+            PAGE_UP | PAGE_DOWN => {
+                if self.soft_wrap {
+                    self.move_to_screen_line(if key == PAGE_UP { 0 } else { self.screenrows - 1 });
+                } else if key == PAGE_UP {
+                    self.cy = self.row_off;
+                } else if key == PAGE_DOWN {
+                    self.cy = self.last_screen_row();
+                }
+                for _ in 1..self.screenrows {
+                    self.move_cursor(
+                        if key == PAGE_UP { ARROW_UP } else { ARROW_DOWN }
+                    );
+                }
+            }
+            HOME_KEY => {
+                self.cx = 0;
+            }
+            CTRL_LEFT => {
+                (self.cy, self.cx) = self.word_start_before();
+            }
+            CTRL_RIGHT => {
+                (self.cy, self.cx) = self.word_end_after();
+            }
+            ALT_UP | ALT_DOWN => {
+                self.cy = self.paragraph_boundary(key == ALT_DOWN);
+                self.cx = 0;
+            }
+            END_KEY if self.cy < self.buffer().rows.count() => {
+                self.cx = self.buffer().rows.get_line(self.cy).len();
+            }
             _ => {}
         }
 
-        if self.cy < self.rows.count() {
-            let line = &self.rows.get_line(self.cy);
-            if self.cx > line.len() {
-                self.cx = line.len();
-            }
+        // stay within the row and off the middle of a grapheme cluster
+        if self.cy < self.buffer().rows.count() {
+            let line = &self.buffer().rows.get_line(self.cy);
+            self.cx = line.grapheme_start(self.cx.min(line.len()));
         }
     }
 
-    pub fn process_keypress(&mut self) -> Result<()> {
+    // Returns false once the user has asked to quit.
+    pub fn process_keypress(&mut self) -> Result<bool> {
         let key = self.read_key()?;
+        let key = self.pager_key(key);
+        let mut keep_selection = false;
+        if key != 0 {
+            // a warning only counts towards pressing the same key again
+            if key != CTRL_Q {
+                self.quit_times = QUIT_TIMES;
+            }
+            if key != CTRL_W {
+                self.close_times = QUIT_TIMES;
+            }
+        }
         match key {
             0 => {
-                // nothing
+                return Ok(true);
             }
             CTRL_Q => {
+                if self.any_buffer_dirty() && self.quit_times > 0 {
+                    self.set_status_msg(&format!(
+                        "WARNING!!! File has unsaved changes. Press Ctrl-Q {} more times to quit.",
+                        self.quit_times
+                    ));
+                    self.quit_times -= 1;
+                    return Ok(true);
+                }
                 Self::clear_screen();
-                self.disable_raw_mode();
-                exit(0);
+                Self::reposition_cursor();
+                return Ok(false);
             }
             CTRL_S => {
-                return self.save_file();
+                self.save_file()?;
             }
-            ARROW_UP | ARROW_DOWN | ARROW_LEFT | ARROW_RIGHT => {
-                self.move_cursor(key);
+            CTRL_O => {
+                self.open_buffer_prompt()?;
             }
-            PAGE_UP | PAGE_DOWN => {
-                if key == PAGE_UP {
-                    self.cy = self.row_off;
-                } else if key == PAGE_DOWN {
-                    self.cy = self.row_off + self.screenrows - 1;
-                    if self.cy > self.rows.count() {
-                        self.cy = self.rows.count();
-                    }
-                }
-                for _ in 1..= (self.screenrows - 1) {
-                    self.move_cursor(
-                        if key == PAGE_UP { ARROW_UP } else { ARROW_DOWN }
-                    );
+            CTRL_B => {
+                self.pick_buffer()?;
+            }
+            CTRL_W => {
+                if !self.close_buffer() {
+                    return Ok(true);
                 }
             }
-            HOME_KEY => {
-                self.cx = 0;
+            ALT_PERIOD | ALT_COMMA => {
+                self.next_buffer(key == ALT_PERIOD);
+            }
+            ALT_S | ALT_V => {
+                self.split_window(key == ALT_V);
+            }
+            ALT_O => {
+                self.next_window();
+            }
+            ALT_Q => {
+                self.close_window();
+            }
+            ALT_EQUALS | ALT_MINUS => {
+                self.resize_window(key == ALT_EQUALS);
+            }
+            CTRL_F => {
+                self.find()?;
+            }
+            CTRL_G => {
+                self.go_to_line_prompt()?;
+            }
+            CTRL_R => {
+                self.replace()?;
+            }
+            ALT_E => {
+                self.reopen_with_encoding()?;
+            }
+            ALT_L => {
+                self.toggle_line_ending();
+            }
+            ALT_N => {
+                self.toggle_final_newline();
+            }
+            ALT_B => {
+                self.toggle_bom();
+            }
+            CTRL_C => {
+                self.copy();
+            }
+            CTRL_X => {
+                self.cut();
+            }
+            CTRL_V | CTRL_U => {
+                self.paste();
+            }
+            CTRL_K => {
+                self.kill_line();
+            }
+            MOUSE => {
+                self.handle_mouse();
+                keep_selection = true;
+            }
+            PASTE_START => {
+                let text = self.read_paste()?;
+                self.delete_selection();
+                self.insert_text(&text);
+            }
+            ALT_Y => {
+                self.rotate_kill_ring();
             }
-            END_KEY => {
-                if self.cy < self.rows.count() {
-                    self.cx = self.rows.get_line(self.cy).len();
+            ALT_C => {
+                self.toggle_osc52();
+            }
+            ALT_G => {
+                self.toggle_line_numbers();
+            }
+            ALT_W => {
+                self.toggle_soft_wrap();
+            }
+            CTRL_Z => {
+                self.undo();
+            }
+            CTRL_Y => {
+                self.redo();
+            }
+            _ if is_movement_key(key) => {
+                if !self.buffer().selection.as_ref().is_some_and(|sel| sel.sticky) {
+                    self.buffer_mut().selection = None;
                 }
+                self.move_cursor(key);
+                keep_selection = true;
+            }
+            _ if key & SHIFT != 0 && is_movement_key(key & !SHIFT) => {
+                self.extend_selection(key & !SHIFT);
+                keep_selection = true;
+            }
+            CTRL_SPACE => {
+                self.toggle_mark();
+                keep_selection = true;
+            }
+            TAB if self.buffer().selection.is_none() => {
+                self.insert_tab();
+            }
+            TAB | BACKTAB => {
+                self.indent_lines(key == BACKTAB);
+                keep_selection = true;
+            }
+            ALT_U | ALT_SHIFT_U => {
+                self.change_case_selection(key == ALT_U);
+                keep_selection = true;
             }
             NEWLINE => {
                 self.insert_newline();
             }
-            BACKSPACE | CTRL_H | DEL_KEY => {
-                self.del_char();
+            ALT_BACKSPACE | CTRL_BACKSPACE => {
+                self.delete_word(false);
+            }
+            CTRL_DEL => {
+                self.delete_word(true);
+            }
+            BACKSPACE | CTRL_H => {
+                if !self.delete_selection() {
+                    self.del_char();
+                }
+            }
+            DEL_KEY => {
+                if !self.delete_selection() {
+                    self.del_char_forward();
+                }
+            }
+            _ if key >= ARROW_LEFT => {
+                // unbound special or modified key
             }
             _ => {
-                if let Some(c) = char::from_u32(key as u32) {
-                    self.insert_char(c);    
+                // ESC and other unbound control keys insert nothing
+                if let Some(c) = char::from_u32(key as u32).filter(|c| !c.is_ascii_control()) {
+                    self.insert_char(c);
                 }
             }
         };
-        Ok(())
+        if !keep_selection {
+            self.buffer_mut().selection = None;
+        }
+        if key != CTRL_K {
+            self.kill_ring.end_append();
+        }
+        Ok(true)
     }
 
-    pub fn prompt(&mut self, prompt: &str) -> Result<Option<String>>{
+    pub fn prompt(
+        &mut self,
+        prompt: &str,
+        mut callback: Option<PromptCallback>,
+    ) -> Result<Option<String>>{
         let mut buf = String::new();
         loop {
             self.set_status_msg(&format!("{}: {}", prompt, buf));
             self.refresh_screen()?;
             let key = self.read_key()?;
+            let mut result = None;
             match key {
-                0 => {}
+                0 => continue,
                 BACKSPACE | CTRL_H | DEL_KEY => {
                     buf.pop();
                 }
+                PASTE_START => {
+                    let text = self.read_paste()?;
+                    buf.extend(text.chars().filter(|c| !c.is_control()));
+                }
                 ESCAPE => {
                     self.set_status_msg("");
-                    return Ok(None);
+                    result = Some(None);
                 }
                 NEWLINE => {
                     self.set_status_msg("");
-                    return Ok(Some(buf));
+                    result = Some(Some(buf.clone()));
                 }
                 _ => {
                     if let Some(c) = char::from_u32(key as u32) {
-                        buf.push(c);    
+                        if !c.is_control() {
+                            buf.push(c);
+                        }
                     }
                 }
             }
+            if let Some(callback) = callback.as_mut() {
+                callback(self, &mut buf, key);
+            }
+            if let Some(result) = result {
+                return Ok(result);
+            }
         }
     }
 }
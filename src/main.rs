@@ -1,28 +1,102 @@
+mod buffer;
+mod cli;
+mod clipboard;
 mod editor;
+mod encoding;
 mod terminal;
 mod input;
+mod mouse;
 mod output;
 mod file_io;
+mod goto;
+mod gutter;
 mod operations;
+mod readonly;
 mod rope;
+mod search;
+mod selection;
+mod syntax;
+mod undo;
+mod window;
+mod word;
+mod wrap;
 
+use cli::Command;
 use editor::Editor;
 use anyhow::{Result, Context};
 
 use std::env;
+use std::io::{self, ErrorKind, Write};
+use std::process;
+
+// Print a line for --help or --version; a reader that went away, as in
+// `kilo-rs --help | head -1`, is not an error.
+fn print_stdout(text: &str) -> Result<()> {
+    match writeln!(io::stdout().lock(), "{}", text) {
+        Err(e) if e.kind() != ErrorKind::BrokenPipe => Err(e).context("Failed to write to stdout"),
+        _ => Ok(()),
+    }
+}
 
 fn main() -> Result<()> {
+    let options = match cli::parse_args(env::args().skip(1)) {
+        Ok(Command::Edit(options)) => options,
+        Ok(Command::Help) => return print_stdout(cli::USAGE),
+        Ok(Command::Version) => return print_stdout(&format!("kilo-rs {}", env!("CARGO_PKG_VERSION"))),
+        Err(e) => {
+            eprintln!("kilo-rs: {}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    // read a piped buffer before the terminal is put in raw mode
+    let stdin = if options.files.iter().any(|file| file.name == "-") {
+        Some(terminal::read_piped_stdin()?)
+    } else {
+        None
+    };
+
     let mut editor = Editor::new().context("Failed to initiate editor")?;
+    if let Some(n) = options.tab_stop {
+        editor.tab_stop = n;
+    }
+    // for the buffer the editor starts with and every one made later
+    editor.read_only = options.read_only;
+    editor.buffer_mut().read_only = options.read_only;
+
+    // later messages, such as warnings from loading files, replace the help
+    editor.set_status_msg("HELP: Ctrl-S = save, Ctrl-Q = quit, Ctrl-F = find, Ctrl-R = replace, Ctrl-Z/Ctrl-Y = undo/redo");
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        editor.open_file(&args[1])?;
+    // an explicit --config must exist, the default one may not
+    let config = options.config.or_else(|| syntax::user_syntax_path().filter(|path| path.exists()));
+    if let Some(path) = config {
+        if let Err(e) = editor.load_syntax_file(&path) {
+            editor.set_status_msg(&format!("{:#}", e));
+        }
     }
 
-    editor.set_status_msg("HELP: Ctrl-S = save, Ctrl-Q = quit");
+    let mut stdin = stdin;
+    for file in &options.files {
+        if file.name == "-" {
+            // only the first `-` gets the input
+            let Some(bytes) = stdin.take() else { continue };
+            editor.open_stdin_buffer(&bytes, options.encoding);
+        } else if let Err(e) = editor.open_buffer(&file.name, options.encoding) {
+            // say why and go on with the other files
+            editor.set_status_msg(&format!("{}: {:#}", file.name, e));
+            continue;
+        }
+        if let Some((line, col)) = file.position {
+            editor.go_to(line, col);
+        }
+    }
+    if editor.buffers.len() > 1 {
+        editor.switch_buffer(0);
+    }
 
     loop {
-        editor.refresh_screen();
-        editor.process_keypress()?;
+        editor.refresh_screen()?;
+        if !editor.process_keypress()? {
+            return Ok(());
+        }
     }
 }
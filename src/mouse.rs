@@ -0,0 +1,143 @@
+use crate::editor::Editor;
+use crate::selection::Selection;
+
+// rows scrolled per wheel step
+const WHEEL_ROWS: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MouseAction {
+    Press,
+    Drag,
+    Release,
+    WheelUp,
+    WheelDown,
+}
+
+// A mouse event at a 0-based screen position.
+#[derive(Clone, Copy)]
+pub struct MouseEvent {
+    pub action: MouseAction,
+    pub x: usize,
+    pub y: usize,
+}
+
+impl MouseEvent {
+    // Decode the parameters and final byte of an SGR mouse report,
+    // `ESC [ < button ; x ; y M` (press) or `... m` (release). Only the
+    // left button and the wheel are reported.
+    pub fn parse_sgr(params: &str, last: u8) -> Option<Self> {
+        let mut nums = params.strip_prefix('<')?.split(';').map(|n| n.parse::<usize>().ok());
+        let (button, x, y) = (nums.next()??, nums.next()??, nums.next()??);
+        let action = match (button & !0b11100, last) {
+            // bits 2-4 are the shift/alt/ctrl modifiers
+            (0, b'M') => MouseAction::Press,
+            (0, b'm') => MouseAction::Release,
+            (32, b'M') => MouseAction::Drag,
+            (64, b'M') => MouseAction::WheelUp,
+            (65, b'M') => MouseAction::WheelDown,
+            _ => return None,
+        };
+        Some(Self { action, x: x.saturating_sub(1), y: y.saturating_sub(1) })
+    }
+}
+
+impl Editor {
+    // Handle the mouse event `read_key` stored; a press starts a selection
+    // that dragging extends. Pressing or scrolling over another window
+    // focuses it.
+    pub fn handle_mouse(&mut self) {
+        let Some(mut event) = self.mouse.take() else {
+            return;
+        };
+        if event.action != MouseAction::Drag && event.action != MouseAction::Release {
+            match self.window_at(event.x, event.y) {
+                Some(i) => self.focus_window(i),
+                None => return,
+            }
+        }
+        let rect = self.windows[self.current_window].rect;
+        event.x = event.x.saturating_sub(rect.left);
+        event.y = event.y.saturating_sub(rect.top);
+        match event.action {
+            MouseAction::Press | MouseAction::Drag => {
+                let Some((cy, cx)) = self.screen_to_pos(event.x, event.y) else {
+                    return;
+                };
+                if event.action == MouseAction::Press {
+                    self.buffer_mut().selection = Some(Selection { anchor: (cy, cx), sticky: false });
+                }
+                (self.cy, self.cx) = (cy, cx);
+            }
+            MouseAction::Release => {}
+            MouseAction::WheelUp => {
+                self.row_off = self.row_off.saturating_sub(WHEEL_ROWS);
+                self.wrap_off = 0;
+                self.cy = self.cy.min(self.last_screen_row());
+                self.clamp_cx();
+            }
+            MouseAction::WheelDown => {
+                self.row_off = (self.row_off + WHEEL_ROWS).min(self.buffer().rows.count().saturating_sub(1));
+                self.wrap_off = 0;
+                self.cy = self.cy.max(self.row_off);
+                self.clamp_cx();
+            }
+        }
+    }
+
+    // Buffer position under a screen cell in the text area, clamped to the
+    // text of the row.
+    fn screen_to_pos(&self, x: usize, y: usize) -> Option<(usize, usize)> {
+        if y >= self.screenrows {
+            return None;
+        }
+        let count = self.buffer().rows.count();
+        if count == 0 {
+            return Some((0, 0));
+        }
+        let x = x.saturating_sub(self.gutter_width());
+        if self.soft_wrap {
+            return Some(match self.visual_line_at_screen(y) {
+                Some((cy, line)) => (cy, self.cx_on_visual_line(cy, line, x)),
+                None => (count - 1, self.buffer().rows.get_line(count - 1).len()),
+            });
+        }
+        let cy = (self.row_off + y).min(count - 1);
+        let cx = self.buffer().rows.get_line(cy).rx_to_cx(self.col_off + x, self.tab_stop);
+        Some((cy, cx))
+    }
+
+    fn clamp_cx(&mut self) {
+        if self.cy < self.buffer().rows.count() {
+            self.cx = self.cx.min(self.buffer().rows.get_line(self.cy).len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(params: &str, last: u8) -> Option<(MouseAction, usize, usize)> {
+        MouseEvent::parse_sgr(params, last).map(|e| (e.action, e.x, e.y))
+    }
+
+    #[test]
+    fn buttons_and_wheel() {
+        assert_eq!(parse("<0;10;5", b'M'), Some((MouseAction::Press, 9, 4)));
+        assert_eq!(parse("<0;10;5", b'm'), Some((MouseAction::Release, 9, 4)));
+        assert_eq!(parse("<32;1;1", b'M'), Some((MouseAction::Drag, 0, 0)));
+        assert_eq!(parse("<64;3;4", b'M'), Some((MouseAction::WheelUp, 2, 3)));
+        assert_eq!(parse("<65;3;4", b'M'), Some((MouseAction::WheelDown, 2, 3)));
+        // with shift, alt and ctrl held
+        assert_eq!(parse("<28;2;2", b'M'), Some((MouseAction::Press, 1, 1)));
+    }
+
+    #[test]
+    fn ignores_other_reports() {
+        assert_eq!(parse("<2;1;1", b'M'), None); // right button
+        assert_eq!(parse("<1;1;1", b'm'), None);
+        assert_eq!(parse("0;1;1", b'M'), None);
+        assert_eq!(parse("<0;1", b'M'), None);
+        assert_eq!(parse("<0;x;1", b'M'), None);
+    }
+}
@@ -1,55 +1,238 @@
 use crate::editor::{Editor, EditorRow};
-use anyhow::Result;
+use crate::encoding::{escape_raw_range, raw_byte};
+use crate::undo::EditKind;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+// columns per tab unless set from the command line
+pub const TAB_STOP: usize = 8;
+
+// Terminal columns taken by a printable char: 2 for wide East Asian chars,
+// 0 for combining marks.
+pub fn display_width(c: char) -> usize {
+    c.width().unwrap_or(1)
+}
 
 impl Editor {
     pub fn append_row(&mut self, chars: &str) {
-        self.rows.insert_line(self.rows.count(), EditorRow::new(chars));
+        let rows = &mut self.buffer_mut().rows;
+        rows.insert_line(rows.count(), EditorRow::new(chars));
     }
 
     pub fn insert_row(&mut self, at: usize, chars: &str) {
-        self.rows.insert_line(at, EditorRow::new(chars));
+        self.buffer_mut().rows.insert_line(at, EditorRow::new(chars));
     }
 
     pub fn insert_char(&mut self, c: char) {
-        if self.cy == self.rows.count() {
+        if self.refuse_read_only() {
+            return;
+        }
+        if raw_byte(c).is_some() {
+            // a typed char that looks like a raw byte goes in as its bytes
+            self.insert_text(&escape_raw_range(&c.to_string()));
+            return;
+        }
+        let (cx, cy) = (self.cx, self.cy);
+        let edit = self.begin_edit(cy, 1);
+        if cy == self.buffer().rows.count() {
             self.append_row("");
         }
-        self.rows.get_line_mut(self.cy).insert_char(self.cx, c);
+        self.buffer_mut().rows.get_line_mut(cy).insert_char(cx, c);
+        self.update_rows(cy, 1);
         self.cx += 1;
-        self.dirty = true;
+        self.commit_edit(edit, 1, EditKind::InsertChar);
     }
     
+    // Insert a tab, or spaces up to the next indent stop when the filetype
+    // indents with spaces.
+    pub fn insert_tab(&mut self) {
+        let buffer = self.buffer();
+        match buffer.syntax.as_ref().filter(|syntax| syntax.expand_tabs) {
+            Some(syntax) => {
+                let width = syntax.indent_width.max(1);
+                let rx = if self.cy < buffer.rows.count() {
+                    buffer.rows.get_line(self.cy).cx_to_rx(self.cx, self.tab_stop)
+                } else {
+                    0
+                };
+                for _ in 0..width - rx % width {
+                    self.insert_char(' ');
+                }
+            }
+            None => self.insert_char('\t'),
+        }
+    }
+
     pub fn insert_newline(&mut self) {
-        if self.cx == 0 {
-            self.insert_row(self.cy, "");
-        } else if self.cx == self.rows.get_line(self.cy).len() {
-            self.insert_row(self.cy + 1, "");
+        if self.refuse_read_only() {
+            return;
+        }
+        let (cx, cy) = (self.cx, self.cy);
+        let edit = self.begin_edit(cy, 1);
+        let rows = &self.buffer().rows;
+        let line_ending = (cy < rows.count())
+            .then(|| rows.get_line(cy).line_ending)
+            .flatten();
+        if cx == 0 {
+            self.insert_row(cy, "");
+        } else if cx == rows.get_line(cy).len() {
+            self.insert_row(cy + 1, "");
         } else {
-            let row = self.rows.get_line_mut(self.cy);
+            let row = self.buffer_mut().rows.get_line_mut(cy);
             let mut chars = std::mem::take(&mut row.chars);
-            let split_at = chars.char_indices().nth(self.cx).map(|(i, _)| i).unwrap();
+            let split_at = chars.char_indices().nth(cx).map(|(i, _)| i).unwrap();
             let right_chars = chars.split_off(split_at);
-            self.insert_row(self.cy + 1, &right_chars);
-            self.rows.get_line_mut(self.cy).update_chars(&chars);
+            self.insert_row(cy + 1, &right_chars);
+            self.buffer_mut().rows.get_line_mut(cy).update_chars(&chars);
         }
+        let rows = &mut self.buffer_mut().rows;
+        if cy + 1 < rows.count() {
+            // the row's own line ending now ends the lower half
+            rows.get_line_mut(cy).line_ending = None;
+            rows.get_line_mut(cy + 1).line_ending = line_ending;
+        }
+        self.update_rows(cy, 2);
         self.cx = 0;
         self.cy += 1;
-        self.dirty = true;
+        self.commit_edit(edit, 2, EditKind::Other);
     }
 
     pub fn del_char(&mut self) {
-        if self.cy == self.rows.count() { return }
-        if self.cx == 0 && self.cy == 0 { return }
-        if self.cx > 0 {
-            self.rows.get_line_mut(self.cy).del_char(self.cx - 1);
-            self.cx -= 1;
+        if self.refuse_read_only() {
+            return;
+        }
+        let (cx, cy) = (self.cx, self.cy);
+        if cy == self.buffer().rows.count() { return }
+        if cx == 0 && cy == 0 { return }
+        if cx > 0 {
+            let edit = self.begin_edit(cy, 1);
+            let row = self.buffer_mut().rows.get_line_mut(cy);
+            let start = row.prev_grapheme(cx);
+            row.replace_range(start, cx, "");
+            self.update_rows(cy, 1);
+            self.cx = start;
+            self.commit_edit(edit, 1, EditKind::Other);
         } else {
-            self.cx = self.rows.get_line(self.cy - 1).len();
-            let row = self.rows.delete_line(self.cy);
-            self.rows.get_line_mut(self.cy - 1).append_string(&row.chars);
+            let edit = self.begin_edit(cy - 1, 2);
+            let rows = &mut self.buffer_mut().rows;
+            let row = rows.delete_line(cy);
+            let upper = rows.get_line_mut(cy - 1);
+            let len = upper.len();
+            upper.append_string(&row.chars);
+            upper.line_ending = row.line_ending;
+            self.cx = len;
             self.cy -= 1;
+            self.update_rows(self.cy, 1);
+            self.commit_edit(edit, 1, EditKind::Other);
+        }
+    }
+
+    // Delete the char under the cursor, or join the next row into this one
+    // at the end of a row.
+    pub fn del_char_forward(&mut self) {
+        if self.refuse_read_only() {
+            return;
+        }
+        let (cx, cy) = (self.cx, self.cy);
+        let rows = &self.buffer().rows;
+        if cy == rows.count() { return }
+        let len = rows.get_line(cy).len();
+        if cx == len && cy + 1 == rows.count() { return }
+        if cx < len {
+            let edit = self.begin_edit(cy, 1);
+            let row = self.buffer_mut().rows.get_line_mut(cy);
+            let end = row.next_grapheme(cx);
+            row.replace_range(cx, end, "");
+            self.update_rows(cy, 1);
+            self.commit_edit(edit, 1, EditKind::Other);
+        } else {
+            let edit = self.begin_edit(cy, 2);
+            let rows = &mut self.buffer_mut().rows;
+            let row = rows.delete_line(cy + 1);
+            let upper = rows.get_line_mut(cy);
+            upper.append_string(&row.chars);
+            upper.line_ending = row.line_ending;
+            self.update_rows(cy, 1);
+            self.commit_edit(edit, 1, EditKind::Other);
+        }
+    }
+
+    // Insert `text` at the cursor as one undo step. The lines of a
+    // multi-line text go straight into the rope as new rows.
+    pub fn insert_text(&mut self, text: &str) {
+        if self.refuse_read_only() {
+            return;
+        }
+        if text.is_empty() {
+            return;
+        }
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let mut lines = text.split('\n');
+        let (cx, cy) = (self.cx, self.cy);
+        let edit = self.begin_edit(cy, 1);
+        if cy == self.buffer().rows.count() {
+            self.append_row("");
+        }
+        let row = self.buffer_mut().rows.get_line_mut(cy);
+        let len = row.len();
+        let tail: String = row.chars.chars().skip(cx).collect();
+        let first = lines.next().unwrap_or_default();
+        row.replace_range(cx, len, first);
+        self.cx += first.chars().count();
+        let mut count = 1;
+        for line in lines {
+            self.insert_row(cy + count, line);
+            self.cx = line.chars().count();
+            count += 1;
+        }
+        let last = cy + count - 1;
+        let rows = &mut self.buffer_mut().rows;
+        if last > cy {
+            let line_ending = rows.get_line_mut(cy).line_ending.take();
+            rows.get_line_mut(last).line_ending = line_ending;
+        }
+        rows.get_line_mut(last).append_string(&tail);
+        self.update_rows(cy, count);
+        self.cy = last;
+        self.commit_edit(edit, count, EditKind::Other);
+    }
+
+    // Text between two (row, col) positions, rows joined with '\n'.
+    pub fn text_range(&self, (r0, c0): (usize, usize), (r1, c1): (usize, usize)) -> String {
+        let mut text = String::new();
+        for (row, line) in (r0..=r1).zip(self.buffer().rows.from_index(r0)) {
+            let start = if row == r0 { c0 } else { 0 };
+            let end = if row == r1 { c1 } else { line.len() };
+            text.extend(line.chars.chars().skip(start).take(end.saturating_sub(start)));
+            if row < r1 {
+                text.push('\n');
+            }
         }
-        self.dirty = true;
+        text
+    }
+
+    // Delete the text between two positions as one undo step, leaving the
+    // cursor at the start.
+    pub fn delete_range(&mut self, (r0, c0): (usize, usize), (r1, c1): (usize, usize)) {
+        if self.refuse_read_only() {
+            return;
+        }
+        let edit = self.begin_edit(r0, r1 - r0 + 1);
+        let rows = &mut self.buffer_mut().rows;
+        let last = rows.get_line(r1);
+        let tail: String = last.chars.chars().skip(c1).collect();
+        let line_ending = last.line_ending;
+        for _ in r0..r1 {
+            rows.delete_line(r0 + 1);
+        }
+        let row = rows.get_line_mut(r0);
+        row.line_ending = line_ending;
+        let len = row.len();
+        row.replace_range(c0, len, &tail);
+        self.update_rows(r0, 1);
+        (self.cy, self.cx) = (r0, c0);
+        self.commit_edit(edit, 1, EditKind::Other);
     }
 }
 
@@ -57,7 +240,10 @@ impl EditorRow {
     pub fn new(chars: &str) -> Self {
         Self {
             chars: chars.to_string(),
-            render: Self::render_from_chars(chars),
+            render: String::new(), // made by `Editor::update_rows`
+            hl: Vec::new(),
+            hl_open_comment: false,
+            line_ending: None,
         }
     }
 
@@ -71,57 +257,132 @@ impl EditorRow {
         } else {
             self.chars.push(c);
         }
-        self.update_render();
-    }
-
-    pub fn del_char(&mut self, at: usize) {
-        if at > self.chars.chars().count() { return }
-        if let Some((start, c)) = self.get_char(at) {
-            let end = start + c.len_utf8();
-            self.chars.replace_range(start..end, "");
-        }
-        self.update_render();
     }
 
     pub fn append_string(&mut self, str: &str) {
         self.chars.push_str(str);
-        self.update_render();
+    }
+
+    // Byte offset of char `at`, or the end of the row.
+    fn byte_at(&self, at: usize) -> usize {
+        self.get_char(at).map_or(self.chars.len(), |(i, _)| i)
+    }
+
+    pub fn replace_range(&mut self, start: usize, end: usize, with: &str) {
+        let mut chars = self.chars[..self.byte_at(start)].to_string();
+        chars.push_str(with);
+        chars.push_str(&self.chars[self.byte_at(end)..]);
+        self.update_chars(&chars);
+    }
+
+    // Char index of the grapheme cluster boundary after `cx`.
+    pub fn next_grapheme(&self, cx: usize) -> usize {
+        self.chars[self.byte_at(cx)..]
+            .graphemes(true)
+            .next()
+            .map_or(cx, |g| cx + g.chars().count())
+    }
+
+    // Char index of the grapheme cluster boundary before `cx`.
+    pub fn prev_grapheme(&self, cx: usize) -> usize {
+        self.chars[..self.byte_at(cx)]
+            .graphemes(true)
+            .next_back()
+            .map_or(cx, |g| cx - g.chars().count())
+    }
+
+    // Start of the grapheme cluster holding char `cx`.
+    pub fn grapheme_start(&self, cx: usize) -> usize {
+        let mut start = 0;
+        for g in self.chars.graphemes(true) {
+            let end = start + g.chars().count();
+            if end > cx {
+                break;
+            }
+            start = end;
+        }
+        start
     }
 
     pub fn update_chars(&mut self, chars: &str) {
         self.chars = chars.to_string();
-        self.update_render();
     }
 
-    pub fn update_render(&mut self) {
-        self.render = Self::render_from_chars(&self.chars);
+    pub fn update_render(&mut self, tab_stop: usize) {
+        self.render = Self::render_from_chars(&self.chars, tab_stop);
+    }
+
+    // Columns taken by `c` when rendered at column `rx`: tabs expand to the
+    // next tab stop, control chars show as `^X`, C1 controls (which Latin-1
+    // makes of bytes 0x80-0x9F) as `\u{9B}` and raw bytes as `\xNN`.
+    fn render_width(c: char, rx: usize, tab_stop: usize) -> usize {
+        if c == '\t' {
+            tab_stop - rx % tab_stop
+        } else if raw_byte(c).is_some() {
+            4
+        } else if c.is_ascii_control() {
+            2
+        } else if c.is_control() {
+            6
+        } else {
+            display_width(c)
+        }
     }
 
-    fn render_from_chars(chars: &str) -> String {
-        let tabs_num = chars.matches('\t').count();
-        let mut render = String::with_capacity(chars.chars().count() + tabs_num * 7 + 1);
+    fn render_from_chars(chars: &str, tab_stop: usize) -> String {
+        let mut render = String::with_capacity(chars.len());
+        let mut rx = 0;
         for c in chars.chars() {
+            let width = Self::render_width(c, rx, tab_stop);
             if c == '\t' {
-                render.push(' ');
-                while render.chars().count() % 8 != 0 {
-                    render.push(' ');
-                }
+                render.extend(std::iter::repeat_n(' ', width));
+            } else if let Some(b) = raw_byte(c) {
+                render.push_str(&format!("\\x{:02X}", b));
+            } else if c.is_ascii_control() {
+                render.push('^');
+                render.push((c as u8 ^ 0x40) as char);
+            } else if c.is_control() {
+                render.push_str(&format!("\\u{{{:02X}}}", c as u32));
             } else {
                 render.push(c);
             }
+            rx += width;
         }
         render
     }
 
-    pub fn cx_to_rx(&self, cx: usize) -> usize {
-        let mut rx = 0usize;
-        for i in 0..cx {
-            if self.chars.chars().nth(i) == Some('\t') {
-                rx += (8 - 1) - (rx % 8)
+    pub fn cx_to_rx(&self, cx: usize, tab_stop: usize) -> usize {
+        self.chars
+            .chars()
+            .take(cx)
+            .fold(0, |rx, c| rx + Self::render_width(c, rx, tab_stop))
+    }
+
+    // Inverse of `cx_to_rx`: the start of the grapheme cluster covering
+    // render column `rx`, or the end of the row when `rx` is past it.
+    pub fn rx_to_cx(&self, rx: usize, tab_stop: usize) -> usize {
+        let mut cur_rx = 0;
+        let mut cx = 0;
+        for g in self.chars.graphemes(true) {
+            cur_rx = g.chars().fold(cur_rx, |rx, c| rx + Self::render_width(c, rx, tab_stop));
+            if cur_rx > rx {
+                return cx;
+            }
+            cx += g.chars().count();
+        }
+        cx
+    }
+
+    // The first render column at or after `rx` where a char starts.
+    pub fn rx_boundary_from(&self, rx: usize, tab_stop: usize) -> usize {
+        let mut cur_rx = 0;
+        for c in self.chars.chars() {
+            if cur_rx >= rx {
+                break;
             }
-            rx += 1;
+            cur_rx += Self::render_width(c, cur_rx, tab_stop);
         }
-        rx
+        cur_rx.max(rx)
     }
 
     pub fn len(&self) -> usize {
@@ -1,7 +1,10 @@
-use crate::editor::Editor;
+use crate::editor::{Editor, EditorRow};
+use crate::operations::display_width;
+use crate::syntax::Highlight;
+use crate::window::{Pane, View};
 use anyhow::{Context, Result};
 use std::io::{self, Write};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::SystemTime;
 
 const CLEAR_SCREEN_CMD: &str = "\x1b[2J";
 const CLEAR_LINE_CMD: &str = "\x1b[K";
@@ -9,62 +12,236 @@ const REPOSITION_CURSOR_CMD: &str = "\x1b[H";
 const HIDE_CURSOR_CMD: &str = "\x1b[?25l";
 const SHOW_CURSOR_CMD: &str = "\x1b[?25h";
 const INVERT_COLOR_CMD: &str = "\x1b[7m";
-const NORMAL_COLOR_CMD: &str = "\x1b[m";
+const NO_INVERT_COLOR_CMD: &str = "\x1b[27m";
+pub const NORMAL_COLOR_CMD: &str = "\x1b[m";
+// shown in the last column of a row that continues on the next line
+const WRAP_MARKER: &str = "\x1b[90m\\\x1b[m";
+// drawn between windows side by side
+const SEPARATOR: &str = "\u{2502}";
 
-impl Editor {
-    fn draw_rows_str(&self) -> String {
+impl Pane<'_> {
+    // Move to row `y` of the window.
+    fn window_line_str(&self, y: usize) -> String {
+        let rect = self.window.rect;
+        format!("\x1b[{};{}H", rect.top + y + 1, rect.left + 1)
+    }
+
+    // Blank the rest of a window row after `drawn` columns.
+    fn pad_line(&self, buf: &mut String, drawn: usize) {
+        buf.push_str(&" ".repeat(self.screencols.saturating_sub(drawn)));
+    }
+
+    pub fn draw_rows_str(&self) -> String {
         let mut buf = String::new();
-        let mut iter = self.rows.from_index(self.row_off);
-        for y in 0..=(self.screenrows - 1) {
-            if let Some(line) = iter.next() { 
-                let row_str = &line.render;
-                if row_str.chars().count() > self.col_off {
-                    let (start, _) = row_str.char_indices().nth(self.col_off).unwrap();
-                    let end = if let Some((idx, _)) =
-                        row_str.char_indices().nth(self.col_off + self.screencols)
-                    {
-                        idx
+        if self.editor.soft_wrap {
+            self.draw_wrapped_rows(&mut buf);
+            return buf;
+        }
+        let row_off = self.view.row_off;
+        let mut iter = self.buffer.rows.from_index(row_off);
+        for y in 0..self.screenrows {
+            buf.push_str(&self.window_line_str(y));
+            let line = iter.next();
+            buf.push_str(&self.draw_gutter(line.map(|_| y + row_off)));
+            let drawn = if let Some(line) = line {
+                self.draw_render_range(&mut buf, y + row_off, line, self.view.col_off, self.text_cols())
+            } else {
+                buf.push('~');
+                1
+            };
+            self.pad_line(&mut buf, self.gutter_width() + drawn);
+        }
+        buf
+    }
+
+    // Like `draw_rows_str`, but each row takes as many screen lines as it
+    // wraps to, starting `wrap_off` visual lines into the top row.
+    fn draw_wrapped_rows(&self, buf: &mut String) {
+        let width = self.wrap_width();
+        let row_off = self.view.row_off;
+        let mut iter = self.buffer.rows.from_index(row_off).enumerate();
+        let mut current = iter.next().map(|(i, line)| (i + row_off, line, line.wrap_starts(width)));
+        let mut visual_line = self.view.wrap_off;
+        for y in 0..self.screenrows {
+            buf.push_str(&self.window_line_str(y));
+            let drawn = match &current {
+                Some((row, line, starts)) => {
+                    buf.push_str(&self.draw_gutter((visual_line == 0).then_some(*row)));
+                    let start = starts[visual_line];
+                    let next = starts.get(visual_line + 1).copied();
+                    let len = next.unwrap_or(usize::MAX) - start;
+                    let mut drawn = self.draw_render_range(buf, *row, line, start, len.min(width));
+                    if next.is_some() && width < self.text_cols() {
+                        // pad to the right edge and mark the continuation
+                        buf.push_str(&" ".repeat(width - drawn));
+                        buf.push_str(WRAP_MARKER);
+                        drawn = width + 1;
+                    }
+                    if next.is_some() {
+                        visual_line += 1;
                     } else {
-                        row_str.len()
-                    };
-                    buf.push_str(&row_str[start..end]);
+                        current = iter.next().map(|(i, line)| (i + row_off, line, line.wrap_starts(width)));
+                        visual_line = 0;
+                    }
+                    drawn
                 }
-            } else {
-                buf.push_str("~");
-            }
+                None => {
+                    buf.push_str(&self.draw_gutter(None));
+                    buf.push('~');
+                    1
+                }
+            };
+            self.pad_line(buf, self.gutter_width() + drawn);
+        }
+    }
 
-            buf.push_str(CLEAR_LINE_CMD);
-            buf.push_str("\r\n")
+    // Draw `len` render columns of `line` (file row `row`) from column
+    // `start`, with syntax colors and inverted search match and selection.
+    // A wide char cut by either edge is drawn as spaces. Returns the number
+    // of columns drawn.
+    fn draw_render_range(&self, buf: &mut String, row: usize, line: &EditorRow, start: usize, len: usize) -> usize {
+        // render columns of the search match on this row, if any
+        let found = match self.editor.search_hl {
+            Some((hl_row, start, end)) if self.focused && hl_row == row => {
+                let tab_stop = self.editor.tab_stop;
+                (line.cx_to_rx(start, tab_stop), line.cx_to_rx(end, tab_stop))
+            }
+            _ => (0, 0),
+        };
+        let selected = self.selection_cols(row, line).unwrap_or((0, 0));
+        let end = start.saturating_add(len);
+        let mut inverted = false;
+        let mut color = Highlight::Normal.color();
+        let mut col = 0;
+        let mut visible = false; // whether the last base char was drawn
+        for (i, c) in line.render.chars().enumerate() {
+            let rx = col;
+            let width = display_width(c);
+            col += width;
+            if width > 0 {
+                visible = start <= rx && col <= end;
+                if !visible {
+                    // cut by the left or the right edge
+                    let shown = col.min(end).saturating_sub(rx.max(start));
+                    buf.push_str(&" ".repeat(shown));
+                    if col >= end {
+                        break;
+                    }
+                    continue;
+                }
+            } else if !visible {
+                continue; // combining mark of a char off screen
+            }
+            let invert = (found.0 <= rx && rx < found.1)
+                || (selected.0 <= rx && rx < selected.1);
+            if invert != inverted {
+                inverted = !inverted;
+                buf.push_str(if inverted { INVERT_COLOR_CMD } else { NO_INVERT_COLOR_CMD });
+            }
+            let c_color = line.hl.get(i).copied().unwrap_or(Highlight::Normal).color();
+            if c_color != color {
+                color = c_color;
+                buf.push_str(&format!("\x1b[{}m", color));
+            }
+            buf.push(c);
         }
-        buf
+        if inverted || color != Highlight::Normal.color() {
+            buf.push_str(NORMAL_COLOR_CMD);
+        }
+        col.min(end).saturating_sub(start)
     }
 
-    fn draw_status_bar(&self) -> String {
-        let mut bar = String::new();
+    pub fn draw_status_bar(&self) -> String {
+        let mut bar = self.window_line_str(self.screenrows);
         bar.push_str(INVERT_COLOR_CMD);
+        let buffer = self.buffer;
         let status_left = format!(
-            " {:.20} - {} lines{}",
-            self.file_name,
-            self.rows.count(),
-            if self.dirty { " modified" } else { "" }
+            " {:.20}{} - {} lines{}{}",
+            buffer.file_name,
+            if buffer.read_only { " [RO]" } else { "" },
+            buffer.rows.count(),
+            if buffer.dirty { " modified" } else { "" },
+            self.editor.buffer_position(self.window.buffer)
         );
-        let status_right = format!(" {}:{} ", self.cy, self.cx);
-        let space_len = self.screencols - status_left.len() - status_right.len() - 2;
-        let space_len = if space_len > 0 { space_len } else { 0 };
-        let space = " ".repeat(space_len);
-        let status = format!("{status_left}{space}{status_right}");
+        let status_right = format!(
+            " {} | {} {}{}{} | {}:{} ",
+            buffer.syntax.as_ref().map_or("no ft", |syntax| syntax.filetype.as_str()),
+            buffer.format.encoding,
+            buffer.format.line_ending,
+            if buffer.format.bom { " BOM" } else { "" },
+            if buffer.format.final_newline { "" } else { " noeol" },
+            self.view.cy,
+            self.view.cx
+        );
+        // drop the right part, then truncate the left, when the terminal is narrow
+        let mut status: String = status_left.chars().take(self.screencols).collect();
+        let left_len = status.chars().count();
+        let right_len = status_right.chars().count();
+        if left_len + right_len <= self.screencols {
+            status.push_str(&" ".repeat(self.screencols - left_len - right_len));
+            status.push_str(&status_right);
+        } else {
+            status.push_str(&" ".repeat(self.screencols - left_len));
+        }
         bar.push_str(&status);
         bar.push_str(NORMAL_COLOR_CMD);
-        bar.push_str("\r\n");
         bar
     }
 
     pub fn move_cursor_str(&self) -> String {
-        format!(
-            "\x1b[{};{}H",
-            self.cy - self.row_off + 1,
-            self.rx - self.col_off + 1
-        )
+        let view = self.view;
+        let (y, x) = if self.editor.soft_wrap {
+            (self.cursor_screen_row(), self.cursor_visual_pos().1)
+        } else {
+            (view.cy - view.row_off, self.rx() - view.col_off)
+        };
+        let rect = self.window.rect;
+        format!("\x1b[{};{}H", rect.top + y + 1, rect.left + self.gutter_width() + x + 1)
+    }
+
+    // The view scrolled so the cursor is on screen.
+    pub fn scrolled(&self) -> View {
+        if self.editor.soft_wrap {
+            return self.scrolled_wrapped();
+        }
+        let mut view = self.view;
+        let rx = self.rx();
+        if view.cy < view.row_off {
+            view.row_off = view.cy;
+        }
+        if view.cy >= view.row_off + self.screenrows {
+            view.row_off = view.cy - self.screenrows + 1;
+        }
+        if rx < view.col_off {
+            view.col_off = rx;
+        }
+        // keep the whole char under the cursor on screen, and never start
+        // the screen in the middle of a wide char
+        let rows = &self.buffer.rows;
+        let line = (view.cy < rows.count()).then(|| rows.get_line(view.cy));
+        let tab_stop = self.editor.tab_stop;
+        let cursor_width = line.map_or(1, |line| line.cx_to_rx(line.next_grapheme(view.cx), tab_stop) - rx);
+        let right = rx + cursor_width.max(1);
+        let text_cols = self.text_cols();
+        if right > view.col_off + text_cols {
+            let col_off = right.saturating_sub(text_cols);
+            view.col_off = line.map_or(col_off, |line| line.rx_boundary_from(col_off, tab_stop));
+        }
+        view
+    }
+}
+
+impl Editor {
+
+    // The lines between windows side by side.
+    fn draw_separators_str(&self) -> String {
+        let mut buf = String::new();
+        for rect in &self.separators {
+            for y in rect.top..rect.top + rect.height {
+                buf.push_str(&format!("\x1b[{};{}H{}", y + 1, rect.left + 1, SEPARATOR));
+            }
+        }
+        buf
     }
 
     pub fn clear_screen() {
@@ -79,24 +256,17 @@ impl Editor {
         stdout.flush().unwrap();
     }
 
-    pub fn scroll(&mut self) {
+    pub fn update_rx(&mut self) {
         self.rx = 0;
-        if self.cy < self.rows.count() {
-            self.rx = self.rows.get_line(self.cy).cx_to_rx(self.cx);
+        if self.cy < self.buffer().rows.count() {
+            self.rx = self.buffer().rows.get_line(self.cy).cx_to_rx(self.cx, self.tab_stop);
         }
+    }
 
-        if self.cy < self.row_off {
-            self.row_off = self.cy;
-        }
-        if self.cy >= self.row_off + self.screenrows {
-            self.row_off = self.cy - self.screenrows + 1;
-        }
-        if self.rx < self.col_off {
-            self.col_off = self.rx;
-        }
-        if self.rx >= self.col_off + self.screencols {
-            self.col_off = self.rx - self.screencols + 1;
-        }
+    pub fn scroll(&mut self) {
+        self.update_rx();
+        let view = self.current_pane().scrolled();
+        (self.row_off, self.wrap_off, self.col_off) = (view.row_off, view.wrap_off, view.col_off);
     }
 
     pub fn set_status_msg(&mut self, msg: &str) {
@@ -105,7 +275,7 @@ impl Editor {
     }
 
     pub fn draw_msg_bar_str(&self) -> String {
-        let mut buf = String::new();
+        let mut buf = format!("\x1b[{};1H", self.term_rows + 1);
         buf.push_str(CLEAR_LINE_CMD);
         if SystemTime::now()
             .duration_since(self.status_msg_time)
@@ -113,20 +283,28 @@ impl Editor {
             .as_secs()
             < 5
         {
-            buf.push_str(&self.status_msg);
+            buf.extend(self.status_msg.chars().take(self.term_cols));
         }
         buf
     }
 
     pub fn refresh_screen(&mut self) -> Result<()> {
-        self.scroll();
+        self.handle_resize()?;
         let mut buf = String::new();
         buf.push_str(HIDE_CURSOR_CMD);
         buf.push_str(REPOSITION_CURSOR_CMD);
-        buf.push_str(&self.draw_rows_str());
-        buf.push_str(&self.draw_status_bar());
+        self.scroll();
+        // the other windows are drawn scrolled to their cursors, as they
+        // would be when focused
+        for id in self.window_order() {
+            let mut pane = self.pane(id);
+            pane.view = pane.scrolled();
+            buf.push_str(&pane.draw_rows_str());
+            buf.push_str(&pane.draw_status_bar());
+        }
+        buf.push_str(&self.draw_separators_str());
         buf.push_str(&self.draw_msg_bar_str());
-        buf.push_str(&self.move_cursor_str());
+        buf.push_str(&self.current_pane().move_cursor_str());
         buf.push_str(SHOW_CURSOR_CMD);
 
         let mut stdout = io::stdout().lock();
@@ -0,0 +1,27 @@
+use crate::editor::Editor;
+use crate::input::{CTRL_F, CTRL_Q, PAGE_DOWN, PAGE_UP};
+
+impl Editor {
+    // Returns true, after saying so, when the buffer can't be changed.
+    pub fn refuse_read_only(&mut self) -> bool {
+        if self.buffer().read_only {
+            self.set_status_msg("Buffer is read-only");
+        }
+        self.buffer().read_only
+    }
+
+    // In a read-only buffer letters can't be typed, so some act like a
+    // pager: space and b page, q quits and / searches.
+    pub fn pager_key(&self, key: i32) -> i32 {
+        if !self.buffer().read_only {
+            return key;
+        }
+        match u8::try_from(key) {
+            Ok(b' ') => PAGE_DOWN,
+            Ok(b'b') => PAGE_UP,
+            Ok(b'q') => CTRL_Q,
+            Ok(b'/') => CTRL_F,
+            _ => key,
+        }
+    }
+}
@@ -45,7 +45,7 @@ impl<Line> RopeNode<Line> {
         }
     }
 
-    pub fn get_line_mut<'a>(&'a mut self, index: usize) -> &'a mut Line {
+    pub fn get_line_mut(&mut self, index: usize) -> &mut Line {
         match self {
             RopeNode::Leaf(lines) => lines.get_mut(index).unwrap(),
             RopeNode::Internal {
@@ -148,16 +148,13 @@ impl<Line> RopeNode<Line> {
         } = self
         {
             // Try to merge if both children are leaves and combined size is acceptable
-            match (&mut **left, &mut **right) {
-                (RopeNode::Leaf(l_lines), RopeNode::Leaf(r_lines)) => {
-                    if l_lines.len() + r_lines.len() <= MAX_LEAF_SIZE {
-                        let mut merged = Vec::with_capacity(l_lines.len() + r_lines.len());
-                        merged.append(l_lines);
-                        merged.append(r_lines);
-                        *self = RopeNode::Leaf(merged);
-                    }
+            if let (RopeNode::Leaf(l_lines), RopeNode::Leaf(r_lines)) = (&mut **left, &mut **right) {
+                if l_lines.len() + r_lines.len() <= MAX_LEAF_SIZE {
+                    let mut merged = Vec::with_capacity(l_lines.len() + r_lines.len());
+                    merged.append(l_lines);
+                    merged.append(r_lines);
+                    *self = RopeNode::Leaf(merged);
                 }
-                _ => {}
             }
         }
     }
@@ -253,9 +250,10 @@ impl<Line> RopeNode<Line> {
     }
 
     // line iterator
-    pub fn from_index(&self, mut index: usize) -> RopeLinesIterator<Line> {
+    #[allow(clippy::wrong_self_convention)]
+    pub fn from_index(&self, mut index: usize) -> RopeLinesIterator<'_, Line> {
         let mut stack = Vec::new();
-        let mut current_leaf_iter = None;
+        let current_leaf_iter;
 
         let mut node = self;
 
@@ -292,7 +290,7 @@ impl<Line> RopeNode<Line> {
         }
     }
 
-    pub fn lines(&self) -> RopeLinesIterator<Line> {
+    pub fn lines(&self) -> RopeLinesIterator<'_, Line> {
         self.from_index(0)
     }
 
@@ -329,7 +327,7 @@ impl<'a, Line> Iterator for RopeLinesIterator<'a, Line> {
         // Walk up the stack and explore right siblings
         while let Some(parent) = self.stack.pop() {
             if let RopeNode::Internal { right, .. } = parent {
-                self.descend_leftmost(&right);
+                self.descend_leftmost(right);
                 return self.next();
             }
         }
@@ -375,7 +373,7 @@ impl<Line> RopeBuilder<Line> {
 
     fn build_balanced(mut nodes: Vec<RopeNode<Line>>) -> RopeNode<Line> {
         while nodes.len() > 1 {
-            let mut next_level = Vec::with_capacity((nodes.len() + 1) / 2);
+            let mut next_level = Vec::with_capacity(nodes.len().div_ceil(2));
             let mut i = 0;
             while i + 1 < nodes.len() {
                 let left = Box::new(nodes[i].take());
@@ -0,0 +1,385 @@
+use crate::editor::{Editor, EditorRow};
+use crate::encoding::escape_raw_range;
+use crate::input::*;
+use crate::rope::RopeNode;
+use crate::undo::EditKind;
+
+use anyhow::Result;
+use regex::Regex;
+
+struct SearchState {
+    origin: (usize, usize),             // (row, col) where the search started
+    last_match: Option<(usize, usize)>, // (row, col) of the current match
+}
+
+// A replace goes from the cursor to the end of the buffer, then from the
+// top back to where it started.
+struct ReplaceState {
+    origin: (usize, usize), // (row, col) where the replace started
+    from: (usize, usize),   // where to look for the next match
+    inclusive: bool,        // whether a match right at `from` counts
+    wrapped: bool,
+}
+
+impl ReplaceState {
+    fn new(origin: (usize, usize)) -> Self {
+        Self { origin, from: origin, inclusive: true, wrapped: false }
+    }
+
+    // Whether the match at `(row, start)`, found from `from`, is still to
+    // be visited.
+    fn visit(&mut self, row: usize, start: usize) -> bool {
+        if (row, start) < self.from {
+            if self.wrapped {
+                return false;
+            }
+            self.wrapped = true;
+        }
+        !(self.wrapped && (row, start) >= self.origin)
+    }
+
+    // Go on after the match at `start..end` of `row`, now `len` chars long.
+    fn advance(&mut self, row: usize, start: usize, end: usize, len: usize) {
+        if self.wrapped && row == self.origin.0 {
+            // text before the origin changed length
+            self.origin.1 = self.origin.1.max(end) - (end - start) + len;
+        }
+        self.from = (row, start + len);
+        // step past empty matches so they are not found again
+        self.inclusive = start != end;
+    }
+}
+
+pub enum Pattern {
+    Plain(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    // A query with a leading `/` is a regular expression.
+    pub fn parse(query: &str) -> Result<Option<Self>, regex::Error> {
+        match query.strip_prefix('/') {
+            Some("") => Ok(None),
+            Some(re) => Ok(Some(Pattern::Regex(Regex::new(re)?))),
+            None if query.is_empty() => Ok(None),
+            None => Ok(Some(Pattern::Plain(query.to_string()))),
+        }
+    }
+
+    // Char index ranges of every non-overlapping match in `chars`.
+    pub fn find_matches(&self, chars: &str) -> Vec<(usize, usize)> {
+        let byte_ranges: Vec<(usize, usize)> = match self {
+            Pattern::Plain(query) => chars
+                .match_indices(query.as_str())
+                .map(|(i, s)| (i, i + s.len()))
+                .collect(),
+            Pattern::Regex(re) => re.find_iter(chars).map(|m| (m.start(), m.end())).collect(),
+        };
+        let char_idx = |byte: usize| chars[..byte].chars().count();
+        byte_ranges
+            .into_iter()
+            .map(|(start, end)| (char_idx(start), char_idx(end)))
+            .collect()
+    }
+
+    // Replacement text for the match starting at char index `start`, with
+    // `$1`/`${name}` expanded from the regex captures.
+    pub fn expand(&self, chars: &str, start: usize, replacement: &str) -> String {
+        match self {
+            Pattern::Plain(_) => replacement.to_string(),
+            Pattern::Regex(re) => {
+                let byte = chars.char_indices().nth(start).map_or(chars.len(), |(i, _)| i);
+                let mut expanded = String::new();
+                if let Some(caps) = re.captures_at(chars, byte) {
+                    caps.expand(replacement, &mut expanded);
+                }
+                expanded
+            }
+        }
+    }
+}
+
+// Search for `pattern` starting at `from` (row, col), wrapping around the
+// buffer. Returns the row and char range of the match.
+pub fn find_in(
+    rows: &RopeNode<EditorRow>,
+    pattern: &Pattern,
+    from: (usize, usize),
+    forward: bool,
+    inclusive: bool,
+) -> Option<(usize, usize, usize)> {
+    let n = rows.count();
+    if n == 0 {
+        return None;
+    }
+    let (row, col, inclusive) = match (from.0 < n, forward) {
+        (true, _) => (from.0, from.1, inclusive),
+        (false, true) => (0, 0, true),
+        (false, false) => (n - 1, usize::MAX, false),
+    };
+
+    if forward {
+        let lines = rows.from_index(row).chain(rows.lines());
+        for (i, line) in lines.enumerate().take(n + 1) {
+            let matches = pattern.find_matches(&line.chars);
+            let hit = if i == 0 {
+                matches
+                    .into_iter()
+                    .find(|&(start, _)| start > col || (inclusive && start == col))
+            } else {
+                matches.into_iter().next()
+            };
+            if let Some((start, end)) = hit {
+                return Some(((row + i) % n, start, end));
+            }
+        }
+    } else {
+        for i in 0..=n {
+            let y = (row + n * 2 - i) % n;
+            let matches = pattern.find_matches(&rows.get_line(y).chars);
+            let hit = if i == 0 {
+                matches
+                    .into_iter()
+                    .rev()
+                    .find(|&(start, _)| start < col || (inclusive && start == col))
+            } else {
+                matches.into_iter().next_back()
+            };
+            if let Some((start, end)) = hit {
+                return Some((y, start, end));
+            }
+        }
+    }
+    None
+}
+
+impl Editor {
+    pub fn find(&mut self) -> Result<()> {
+        let saved = (self.cx, self.cy, self.row_off, self.wrap_off, self.col_off);
+        let mut state = SearchState {
+            origin: (self.cy, self.cx),
+            last_match: None,
+        };
+        let query = self.prompt(
+            "Search (Use ESC/Arrows/Enter, Alt-R or leading / for regex)",
+            Some(&mut |editor: &mut Editor, query: &mut String, key: i32| {
+                editor.find_callback(&mut state, query, key)
+            }),
+        )?;
+        self.search_hl = None;
+        if query.is_none() {
+            (self.cx, self.cy, self.row_off, self.wrap_off, self.col_off) = saved;
+        }
+        Ok(())
+    }
+
+    fn find_callback(&mut self, state: &mut SearchState, query: &mut String, key: i32) {
+        if key == ALT_R {
+            if query.starts_with('/') {
+                query.remove(0);
+            } else {
+                query.insert(0, '/');
+            }
+        }
+        let pattern = match Pattern::parse(query) {
+            Ok(Some(pattern)) => pattern,
+            _ => {
+                state.last_match = None;
+                self.search_hl = None;
+                return;
+            }
+        };
+        let found = match key {
+            NEWLINE | ESCAPE => return,
+            ARROW_RIGHT | ARROW_DOWN => {
+                let from = state.last_match.unwrap_or(state.origin);
+                self.find_next(&pattern, from, true, state.last_match.is_none())
+            }
+            ARROW_LEFT | ARROW_UP => {
+                let from = state.last_match.unwrap_or(state.origin);
+                self.find_next(&pattern, from, false, false)
+            }
+            _ => self.find_next(&pattern, state.origin, true, true),
+        };
+
+        if let Some((row, start, end)) = found {
+            state.last_match = Some((row, start));
+            self.cy = row;
+            self.cx = start;
+            self.row_off = self.buffer().rows.count(); // scroll the match to the top
+            self.search_hl = Some((row, start, end));
+        } else {
+            state.last_match = None;
+            self.search_hl = None;
+        }
+    }
+
+    // Search the buffer, see `find_in`.
+    pub fn find_next(
+        &self,
+        pattern: &Pattern,
+        from: (usize, usize),
+        forward: bool,
+        inclusive: bool,
+    ) -> Option<(usize, usize, usize)> {
+        find_in(&self.buffer().rows, pattern, from, forward, inclusive)
+    }
+
+    pub fn replace(&mut self) -> Result<()> {
+        if self.refuse_read_only() {
+            return Ok(());
+        }
+        let Some(query) = self.prompt("Replace (leading / for regex)", None)? else {
+            return Ok(());
+        };
+        let pattern = match Pattern::parse(&query) {
+            Ok(Some(pattern)) => pattern,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                self.set_status_msg(&format!("Invalid regex: {}", e));
+                return Ok(());
+            }
+        };
+        let Some(replacement) = self.prompt(&format!("Replace {} with", query), None)? else {
+            return Ok(());
+        };
+        let replacement = escape_raw_range(&replacement);
+
+        let saved = (self.row_off, self.wrap_off, self.col_off);
+        let mut state = ReplaceState::new((self.cy, self.cx));
+        let mut count = 0;
+        let mut replace_all = false;
+        while let Some((row, start, end)) = self.find_next(&pattern, state.from, true, state.inclusive) {
+            if !state.visit(row, start) {
+                break;
+            }
+            self.cy = row;
+            self.cx = start;
+            self.search_hl = Some((row, start, end));
+
+            let mut replace = replace_all;
+            if !replace {
+                self.set_status_msg("Replace? (y)es (n)o (a)ll (q)uit");
+                self.refresh_screen()?;
+                match self.read_confirm_key()? {
+                    'y' => replace = true,
+                    'a' => {
+                        replace = true;
+                        replace_all = true;
+                    }
+                    'n' => {}
+                    _ => break,
+                }
+            }
+
+            if replace {
+                let text = pattern.expand(&self.buffer().rows.get_line(row).chars, start, &replacement);
+                let len = text.chars().count();
+                let edit = self.begin_edit(row, 1);
+                self.buffer_mut().rows.get_line_mut(row).replace_range(start, end, &text);
+                self.update_rows(row, 1);
+                self.cx = start + len;
+                // the whole replace is one undo step
+                let kind = if count == 0 { EditKind::Other } else { EditKind::Joined };
+                self.commit_edit(edit, 1, kind);
+                count += 1;
+                state.advance(row, start, end, len);
+            } else {
+                state.advance(row, start, end, end - start);
+            }
+        }
+
+        self.search_hl = None;
+        (self.cy, self.cx) = state.origin;
+        (self.row_off, self.wrap_off, self.col_off) = saved;
+        self.clamp_cursor();
+        self.set_status_msg(&format!("{} replacements made", count));
+        Ok(())
+    }
+
+    // Wait for one of y/n/a/q; Escape counts as q.
+    fn read_confirm_key(&mut self) -> Result<char> {
+        loop {
+            match self.read_key()? {
+                ESCAPE => return Ok('q'),
+                0 => self.refresh_screen()?, // redraw after a resize
+                key => {
+                    if let Some(c @ ('y' | 'n' | 'a' | 'q')) = char::from_u32(key as u32) {
+                        return Ok(c);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(lines: &[&str]) -> RopeNode<EditorRow> {
+        RopeNode::from_lines(lines.iter().map(|line| EditorRow::new(line)).collect())
+    }
+
+    fn text(rows: &RopeNode<EditorRow>) -> Vec<String> {
+        rows.lines().map(|row| row.chars.clone()).collect()
+    }
+
+    // Answer "all" to a replace started at `origin`, as `Editor::replace` does.
+    fn replace_all(rows: &mut RopeNode<EditorRow>, query: &str, replacement: &str, origin: (usize, usize)) -> usize {
+        let pattern = Pattern::parse(query).unwrap().unwrap();
+        let mut state = ReplaceState::new(origin);
+        let mut count = 0;
+        while let Some((row, start, end)) = find_in(rows, &pattern, state.from, true, state.inclusive) {
+            if !state.visit(row, start) {
+                break;
+            }
+            let text = pattern.expand(&rows.get_line(row).chars, start, replacement);
+            rows.get_line_mut(row).replace_range(start, end, &text);
+            state.advance(row, start, end, text.chars().count());
+            count += 1;
+        }
+        count
+    }
+
+    #[test]
+    fn replace_wraps_back_to_the_cursor_row() {
+        let mut r = rows(&["aaaaaaaaaa"]);
+        assert_eq!(replace_all(&mut r, "a", "bb", (0, 5)), 10);
+        assert_eq!(text(&r), ["b".repeat(20)]);
+
+        let mut r = rows(&["xaxaxa", "x"]);
+        assert_eq!(replace_all(&mut r, "x", "", (0, 3)), 4);
+        assert_eq!(text(&r), ["aaa", ""]);
+    }
+
+    #[test]
+    fn replace_does_not_revisit_its_own_text() {
+        let mut r = rows(&["ab", "ab"]);
+        assert_eq!(replace_all(&mut r, "a", "aa", (1, 0)), 2);
+        assert_eq!(text(&r), ["aab", "aab"]);
+
+        let mut r = rows(&["a a", "a"]);
+        assert_eq!(replace_all(&mut r, "/a", "[$0]", (0, 2)), 3);
+        assert_eq!(text(&r), ["[a] [a]", "[a]"]);
+    }
+
+    #[test]
+    fn find_matches_in_chars() {
+        let plain = Pattern::parse("é").unwrap().unwrap();
+        assert_eq!(plain.find_matches("éaé"), [(0, 1), (2, 3)]);
+        let re = Pattern::parse("/[0-9]+").unwrap().unwrap();
+        assert_eq!(re.find_matches("a12 é345"), [(1, 3), (5, 8)]);
+        assert!(Pattern::parse("").unwrap().is_none());
+        assert!(Pattern::parse("/").unwrap().is_none());
+        assert!(Pattern::parse("/(").is_err());
+    }
+
+    #[test]
+    fn expand_captures() {
+        let re = Pattern::parse(r"/(\w+)=(?P<v>\w+)").unwrap().unwrap();
+        assert_eq!(re.expand("é a=1 b=2", 6, "${v}:$1"), "2:b");
+        let plain = Pattern::parse("a").unwrap().unwrap();
+        assert_eq!(plain.expand("a", 0, "$1"), "$1");
+    }
+}
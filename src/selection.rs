@@ -0,0 +1,175 @@
+use crate::buffer::Buffer;
+use crate::editor::{Editor, EditorRow};
+use crate::undo::EditKind;
+use crate::window::Pane;
+
+pub struct Selection {
+    pub anchor: (usize, usize), // (row, col) where the selection started
+    pub sticky: bool,           // set with Ctrl-Space, kept by plain movement
+}
+
+impl Buffer {
+    // Positions past the last row are treated as the end of the last row.
+    fn clamp_pos(&self, (row, col): (usize, usize)) -> (usize, usize) {
+        let count = self.rows.count();
+        if row < count {
+            (row, col.min(self.rows.get_line(row).len()))
+        } else if count > 0 {
+            (count - 1, self.rows.get_line(count - 1).len())
+        } else {
+            (0, 0)
+        }
+    }
+
+    // Ordered (start, end) of the selection between its anchor and
+    // `cursor`, or None if nothing is selected.
+    fn selection_range(&self, cursor: (usize, usize)) -> Option<((usize, usize), (usize, usize))> {
+        let sel = self.selection.as_ref()?;
+        let anchor = self.clamp_pos(sel.anchor);
+        let cursor = self.clamp_pos(cursor);
+        match anchor.cmp(&cursor) {
+            std::cmp::Ordering::Less => Some((anchor, cursor)),
+            std::cmp::Ordering::Greater => Some((cursor, anchor)),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+}
+
+impl Pane<'_> {
+    // Selected render columns of `row`, for drawing.
+    pub fn selection_cols(&self, row: usize, line: &EditorRow) -> Option<(usize, usize)> {
+        if !self.focused {
+            return None;
+        }
+        let ((r0, c0), (r1, c1)) = self.buffer.selection_range((self.view.cy, self.view.cx))?;
+        if row < r0 || row > r1 {
+            return None;
+        }
+        let tab_stop = self.editor.tab_stop;
+        let start = if row == r0 { line.cx_to_rx(c0, tab_stop) } else { 0 };
+        let end = if row == r1 { line.cx_to_rx(c1, tab_stop) } else { usize::MAX };
+        Some((start, end))
+    }
+}
+
+impl Editor {
+    pub fn toggle_mark(&mut self) {
+        if self.buffer_mut().selection.take().is_some() {
+            self.set_status_msg("Mark cleared");
+        } else {
+            self.buffer_mut().selection = Some(Selection { anchor: (self.cy, self.cx), sticky: true });
+            self.set_status_msg("Mark set");
+        }
+    }
+
+    pub fn extend_selection(&mut self, key: i32) {
+        if self.buffer().selection.is_none() {
+            self.buffer_mut().selection = Some(Selection { anchor: (self.cy, self.cx), sticky: false });
+        }
+        self.move_cursor(key);
+    }
+
+    // Ordered (start, end) of the selection, or None if nothing is selected.
+    pub fn selection_range(&self) -> Option<((usize, usize), (usize, usize))> {
+        self.buffer().selection_range((self.cy, self.cx))
+    }
+
+    pub fn selected_text(&self) -> Option<String> {
+        let (start, end) = self.selection_range()?;
+        Some(self.text_range(start, end))
+    }
+
+    // Delete the selected text as one undo step. Returns false if nothing
+    // was selected.
+    pub fn delete_selection(&mut self) -> bool {
+        let Some((start, end)) = self.selection_range() else {
+            return false;
+        };
+        self.buffer_mut().selection = None;
+        self.delete_range(start, end);
+        true
+    }
+
+    // Rows covered by the selection, or the cursor row; a selection ending
+    // at column 0 does not include that last row.
+    fn selected_rows(&self) -> Option<(usize, usize)> {
+        match self.selection_range() {
+            Some(((r0, _), (r1, 0))) if r1 > r0 => Some((r0, r1 - 1)),
+            Some(((r0, _), (r1, _))) => Some((r0, r1)),
+            None if self.cy < self.buffer().rows.count() => Some((self.cy, self.cy)),
+            None => None,
+        }
+    }
+
+    pub fn indent_lines(&mut self, outdent: bool) {
+        if self.refuse_read_only() {
+            return;
+        }
+        let Some((r0, r1)) = self.selected_rows() else {
+            return;
+        };
+        let (unit, width) = match self.buffer().syntax.as_ref().filter(|syntax| syntax.expand_tabs) {
+            Some(syntax) => (" ".repeat(syntax.indent_width), syntax.indent_width),
+            None => ("\t".to_string(), 1),
+        };
+        let n = r1 - r0 + 1;
+        let edit = self.begin_edit(r0, n);
+        let mut shifts = Vec::with_capacity(n); // chars added (+) or removed (-) per row
+        for row in r0..=r1 {
+            let line = self.buffer_mut().rows.get_line_mut(row);
+            let shift = if outdent {
+                let strip = if line.chars.starts_with('\t') {
+                    1
+                } else {
+                    line.chars.chars().take(width).take_while(|&c| c == ' ').count()
+                };
+                let rest: String = line.chars.chars().skip(strip).collect();
+                line.update_chars(&rest);
+                -(strip as isize)
+            } else if !line.chars.is_empty() {
+                line.update_chars(&format!("{}{}", unit, line.chars));
+                width as isize
+            } else {
+                0
+            };
+            shifts.push(shift);
+        }
+        if shifts.iter().all(|&shift| shift == 0) {
+            return;
+        }
+        self.update_rows(r0, n);
+
+        // keep the cursor and the anchor on the same text; column 0 stays put
+        let shift_col = |(row, col): (usize, usize)| match shifts.get(row.wrapping_sub(r0)) {
+            Some(&shift) if row >= r0 && col > 0 => (row, col.saturating_add_signed(shift)),
+            _ => (row, col),
+        };
+        (self.cy, self.cx) = shift_col((self.cy, self.cx));
+        if let Some(sel) = self.buffer_mut().selection.as_mut() {
+            sel.anchor = shift_col(sel.anchor);
+        }
+        self.commit_edit(edit, n, EditKind::Other);
+    }
+
+    pub fn change_case_selection(&mut self, upper: bool) {
+        if self.refuse_read_only() {
+            return;
+        }
+        let Some(((r0, c0), (r1, c1))) = self.selection_range() else {
+            self.set_status_msg("No selection");
+            return;
+        };
+        let n = r1 - r0 + 1;
+        let edit = self.begin_edit(r0, n);
+        for row in r0..=r1 {
+            let line = self.buffer_mut().rows.get_line_mut(row);
+            let start = if row == r0 { c0 } else { 0 };
+            let end = if row == r1 { c1 } else { line.len() };
+            let text: String = line.chars.chars().skip(start).take(end - start).collect();
+            let text = if upper { text.to_uppercase() } else { text.to_lowercase() };
+            line.replace_range(start, end, &text);
+        }
+        self.update_rows(r0, n);
+        self.commit_edit(edit, n, EditKind::Other);
+    }
+}
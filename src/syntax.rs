@@ -0,0 +1,337 @@
+use crate::editor::{Editor, EditorRow};
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use anyhow::{anyhow, Context, Result};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Highlight {
+    Normal,
+    Comment,
+    MlComment,
+    Keyword,
+    Type,
+    String,
+    Number,
+}
+
+impl Highlight {
+    // ANSI foreground color code
+    pub fn color(self) -> u8 {
+        match self {
+            Highlight::Normal => 39,
+            Highlight::Comment | Highlight::MlComment => 36,
+            Highlight::Keyword => 33,
+            Highlight::Type => 32,
+            Highlight::String => 35,
+            Highlight::Number => 31,
+        }
+    }
+}
+
+pub struct Syntax {
+    pub filetype: String,
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+    pub shebangs: Vec<String>,
+    pub keywords: Vec<String>,
+    pub types: Vec<String>,
+    pub singleline_comment: String,
+    pub multiline_comment: Option<(String, String)>,
+    pub string_delims: Vec<char>,
+    pub highlight_numbers: bool,
+    pub indent_width: usize,
+    pub expand_tabs: bool,
+}
+
+const BUILTIN_SYNTAX: &str = include_str!("../syntax.conf");
+
+fn words(s: &str) -> Vec<String> {
+    s.split_whitespace().map(str::to_string).collect()
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(anyhow!("expected true or false, got '{}'", value)),
+    }
+}
+
+impl Syntax {
+    fn new(filetype: &str) -> Self {
+        Self {
+            filetype: filetype.to_string(),
+            extensions: Vec::new(),
+            filenames: Vec::new(),
+            shebangs: Vec::new(),
+            keywords: Vec::new(),
+            types: Vec::new(),
+            singleline_comment: String::new(),
+            multiline_comment: None,
+            string_delims: Vec::new(),
+            highlight_numbers: false,
+            indent_width: 8,
+            expand_tabs: false,
+        }
+    }
+
+    pub fn builtin() -> Vec<Syntax> {
+        Self::parse(BUILTIN_SYNTAX).expect("built-in syntax.conf is invalid")
+    }
+
+    // Parse language definitions: `[filetype]` sections of `key = value`
+    // lines, where indented lines continue the previous value.
+    pub fn parse(src: &str) -> Result<Vec<Syntax>> {
+        let mut db: Vec<Syntax> = Vec::new();
+        let mut pending: Option<(usize, String, String)> = None;
+        for (i, line) in src.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            if line.starts_with(char::is_whitespace) {
+                if let Some((_, _, value)) = pending.as_mut() {
+                    value.push(' ');
+                    value.push_str(trimmed);
+                    continue;
+                }
+            }
+            Self::flush(&mut db, pending.take())?;
+            if let Some(name) = trimmed.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                db.push(Syntax::new(name.trim()));
+            } else if let Some((key, value)) = trimmed.split_once('=') {
+                pending = Some((i + 1, key.trim().to_string(), value.trim().to_string()));
+            } else {
+                return Err(anyhow!("line {}: expected '[filetype]' or 'key = value'", i + 1));
+            }
+        }
+        Self::flush(&mut db, pending)?;
+        Ok(db)
+    }
+
+    fn flush(db: &mut [Syntax], entry: Option<(usize, String, String)>) -> Result<()> {
+        let Some((lineno, key, value)) = entry else {
+            return Ok(());
+        };
+        let Some(syntax) = db.last_mut() else {
+            return Err(anyhow!("line {}: '{}' outside of a [filetype] section", lineno, key));
+        };
+        syntax
+            .set(&key, &value)
+            .with_context(|| format!("line {}: invalid value for '{}'", lineno, key))
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "extensions" => self.extensions = words(value),
+            "filenames" => self.filenames = words(value),
+            "shebangs" => self.shebangs = words(value),
+            "keywords" => self.keywords = words(value),
+            "types" => self.types = words(value),
+            "comment" => self.singleline_comment = value.to_string(),
+            "multiline_comment" => {
+                self.multiline_comment = match words(value).as_slice() {
+                    [start, end] => Some((start.clone(), end.clone())),
+                    [] => None,
+                    _ => return Err(anyhow!("expected a start and an end delimiter")),
+                }
+            }
+            "strings" => self.string_delims = value.chars().filter(|c| !c.is_whitespace()).collect(),
+            "numbers" => self.highlight_numbers = parse_bool(value)?,
+            "indent_width" => self.indent_width = value.parse()?,
+            "expand_tabs" => self.expand_tabs = parse_bool(value)?,
+            _ => return Err(anyhow!("unknown key")),
+        }
+        Ok(())
+    }
+
+    fn matches_shebang(&self, first_line: &str) -> bool {
+        let Some(cmd) = first_line.strip_prefix("#!") else {
+            return false;
+        };
+        let mut args = cmd.split_whitespace();
+        let mut interpreter = args.next().unwrap_or("").rsplit('/').next().unwrap_or("");
+        if interpreter == "env" {
+            interpreter = args.find(|arg| !arg.starts_with('-')).unwrap_or("");
+        }
+        // `python` also matches `python3`, `python3.12`, ...
+        self.shebangs.iter().any(|name| {
+            interpreter
+                .strip_prefix(name.as_str())
+                .is_some_and(|rest| rest.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        })
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || c == '\0' || ",.()+-/*=~%<>[];{}:&|!?".contains(c)
+}
+
+fn starts_with(render: &[char], at: usize, pat: &str) -> bool {
+    !pat.is_empty() && pat.chars().enumerate().all(|(j, p)| render.get(at + j) == Some(&p))
+}
+
+impl EditorRow {
+    // Highlight `render` given whether a multi-line comment is open at the
+    // start of the row; records whether one is still open at its end.
+    pub fn update_syntax(&mut self, syntax: Option<&Syntax>, mut in_comment: bool) {
+        let render: Vec<char> = self.render.chars().collect();
+        self.hl = vec![Highlight::Normal; render.len()];
+        let Some(syntax) = syntax else {
+            self.hl_open_comment = false;
+            return;
+        };
+
+        let mut prev_sep = true;
+        let mut in_string: Option<char> = None;
+        let mut i = 0;
+        'outer: while i < render.len() {
+            let c = render[i];
+            let prev_hl = if i > 0 { self.hl[i - 1] } else { Highlight::Normal };
+
+            if in_string.is_none() && !in_comment
+                && starts_with(&render, i, &syntax.singleline_comment)
+            {
+                self.hl[i..].fill(Highlight::Comment);
+                break;
+            }
+
+            if let (Some((start, end)), None) = (&syntax.multiline_comment, in_string) {
+                if in_comment {
+                    if starts_with(&render, i, end) {
+                        let len = end.chars().count();
+                        self.hl[i..i + len].fill(Highlight::MlComment);
+                        i += len;
+                        in_comment = false;
+                        prev_sep = true;
+                    } else {
+                        self.hl[i] = Highlight::MlComment;
+                        i += 1;
+                    }
+                    continue;
+                } else if starts_with(&render, i, start) {
+                    let len = start.chars().count();
+                    self.hl[i..i + len].fill(Highlight::MlComment);
+                    i += len;
+                    in_comment = true;
+                    continue;
+                }
+            }
+
+            if let Some(delim) = in_string {
+                self.hl[i] = Highlight::String;
+                if c == '\\' && i + 1 < render.len() {
+                    self.hl[i + 1] = Highlight::String;
+                    i += 2;
+                    continue;
+                }
+                if c == delim {
+                    in_string = None;
+                }
+                i += 1;
+                prev_sep = true;
+                continue;
+            } else if syntax.string_delims.contains(&c) {
+                in_string = Some(c);
+                self.hl[i] = Highlight::String;
+                i += 1;
+                continue;
+            }
+
+            if syntax.highlight_numbers
+                && ((c.is_ascii_digit() && (prev_sep || prev_hl == Highlight::Number))
+                    || (c == '.' && prev_hl == Highlight::Number))
+            {
+                self.hl[i] = Highlight::Number;
+                i += 1;
+                prev_sep = false;
+                continue;
+            }
+
+            if prev_sep {
+                let keywords = syntax.keywords.iter().map(|kw| (kw, Highlight::Keyword));
+                let types = syntax.types.iter().map(|kw| (kw, Highlight::Type));
+                for (kw, class) in keywords.chain(types) {
+                    let len = kw.chars().count();
+                    if starts_with(&render, i, kw)
+                        && render.get(i + len).is_none_or(|&c| is_separator(c))
+                    {
+                        self.hl[i..i + len].fill(class);
+                        i += len;
+                        prev_sep = false;
+                        continue 'outer;
+                    }
+                }
+            }
+
+            prev_sep = is_separator(c);
+            i += 1;
+        }
+        self.hl_open_comment = in_comment;
+    }
+}
+
+// ~/.config/kilo-rs/syntax.conf, honouring $XDG_CONFIG_HOME
+pub fn user_syntax_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("kilo-rs").join("syntax.conf"))
+}
+
+impl Editor {
+    // Add the definitions in `path` to the syntax database, replacing any
+    // existing definition of the same filetype.
+    pub fn load_syntax_file(&mut self, path: &Path) -> Result<()> {
+        let src = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let defs = Syntax::parse(&src).with_context(|| format!("{}", path.display()))?;
+        for def in defs.into_iter().rev() {
+            self.syntax_db.retain(|syntax| syntax.filetype != def.filetype);
+            self.syntax_db.insert(0, Rc::new(def));
+        }
+        Ok(())
+    }
+
+    // Pick a syntax by file name, then extension, then `#!` line.
+    pub fn select_syntax(&mut self) {
+        let path = Path::new(&self.buffer().file_name);
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+        let ext = path.extension().map(|ext| format!(".{}", ext.to_string_lossy()));
+        let first_line = if self.buffer().rows.count() > 0 { self.buffer().rows.get_line(0).chars.as_str() } else { "" };
+
+        let db = &self.syntax_db;
+        self.buffer_mut().syntax = name
+            .and_then(|name| db.iter().find(|syntax| syntax.filenames.contains(&name)))
+            .or_else(|| ext.and_then(|ext| db.iter().find(|syntax| syntax.extensions.contains(&ext))))
+            .or_else(|| db.iter().find(|syntax| syntax.matches_shebang(first_line)))
+            .cloned();
+        self.update_rows(0, self.buffer().rows.count());
+    }
+
+    // Re-render and re-highlight the `count` rows at `at`, then re-highlight
+    // the row after them and keep going only while the open-comment state at
+    // the end of a row changes.
+    pub fn update_rows(&mut self, at: usize, count: usize) {
+        let tab_stop = self.tab_stop;
+        let buffer = self.buffer_mut();
+        let syntax = buffer.syntax.as_deref();
+        let rows = &mut buffer.rows;
+        let mut in_comment = at > 0 && rows.get_line(at - 1).hl_open_comment;
+        for i in at..rows.count() {
+            let row = rows.get_line_mut(i);
+            if i < at + count {
+                row.update_render(tab_stop);
+            }
+            let was_open = row.hl_open_comment;
+            row.update_syntax(syntax, in_comment);
+            in_comment = row.hl_open_comment;
+            if i >= at + count && was_open == in_comment {
+                break;
+            }
+        }
+    }
+}
@@ -1,14 +1,50 @@
 use crate::editor::Editor;
+use crate::encoding::Encoding;
+use crate::mouse::MouseEvent;
 use crate::input::*;
 
+use std::fs::File;
 use std::io::{self, Read, Write};
-use std::os::unix::io::AsFd;
+use std::os::unix::io::{AsFd, AsRawFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use nix::libc;
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::unistd::dup2;
 use nix::sys::termios::{Termios, tcgetattr, tcsetattr,
     LocalFlags, InputFlags, OutputFlags, ControlFlags, SpecialCharacterIndices, SetArg};
 use anyhow::{Context, Result};
 
+// set by the SIGWINCH handler, consumed by `handle_resize`
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+const BRACKETED_PASTE_ON: &str = "\x1b[?2004h";
+const BRACKETED_PASTE_OFF: &str = "\x1b[?2004l";
+// button and drag events, reported in the SGR format
+const MOUSE_ON: &str = "\x1b[?1002h\x1b[?1006h";
+const MOUSE_OFF: &str = "\x1b[?1006l\x1b[?1002l";
+const PASTE_END: &[u8] = b"\x1b[201~";
+// give up on a paste whose end marker never arrives after this many
+// read timeouts (tenths of a second)
+const PASTE_TIMEOUTS: usize = 50;
+
+// Read all of standard input, then make the terminal standard input so keys
+// still come from it, for `kilo-rs -`.
+pub fn read_piped_stdin() -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    io::stdin().read_to_end(&mut bytes).context("Failed to read stdin")?;
+    let tty = File::open("/dev/tty").context("Failed to open /dev/tty")?;
+    dup2(tty.as_raw_fd(), io::stdin().as_raw_fd()).context("Failed to read keys from /dev/tty")?;
+    Ok(bytes)
+}
+
+extern "C" fn on_sigwinch(_: libc::c_int) {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+nix::ioctl_read_bad!(tiocgwinsz, libc::TIOCGWINSZ, libc::winsize);
+
 impl Editor {
-    pub fn enable_raw_mode() -> Result<(Termios, Termios)> {
+    pub fn enable_raw_mode() -> Result<Termios> {
         let stdin = io::stdin();
         let fd = stdin.as_fd();
         let ori_termios = tcgetattr(fd).context("Failed to get terminal attributes")?;
@@ -37,68 +73,169 @@ impl Editor {
         termios.control_chars[SpecialCharacterIndices::VTIME as usize] = 1;
 
         tcsetattr(fd, SetArg::TCSAFLUSH, &termios).context("Failed to set terminal attributes")?;
-        Ok((ori_termios, termios))
+
+        // have the terminal wrap pastes in ESC [ 200~ ... ESC [ 201~ and
+        // report mouse clicks, drags and the wheel
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(BRACKETED_PASTE_ON.as_bytes()).context("Failed to write to stdout")?;
+        stdout.write_all(MOUSE_ON.as_bytes()).context("Failed to write to stdout")?;
+        stdout.flush().context("Failed to flush")?;
+        Ok(ori_termios)
     }
 
     pub fn disable_raw_mode(&self) {
+        let mut stdout = io::stdout().lock();
+        let _ = stdout.write_all(BRACKETED_PASTE_OFF.as_bytes());
+        let _ = stdout.write_all(MOUSE_OFF.as_bytes());
+        let _ = stdout.flush();
         let stdin = io::stdin();
         let fd = stdin.as_fd();
         let _ = tcsetattr(fd, SetArg::TCSAFLUSH, &self.ori_termios).context("Failed to set terminal attributes");
     }
 
-    pub fn read_key(&self) -> Result<i32> {
+    pub fn read_key(&mut self) -> Result<i32> {
         let mut stdin = io::stdin().lock();
         let mut buffer = [0u8; 1];
         loop {
             match stdin.read(&mut buffer) {
+                Ok(0) => return Ok(0), // timed out without a key
                 Ok(_) => break,
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                // a signal such as SIGWINCH arrived, let the caller redraw
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => return Ok(0),
                 Err(e) => return Err(e).context("Failed to read key from stdin"),
             }
         }
-        if buffer[0] == b'\x1b' {
-            let mut seq = [0u8; 3];
-            if stdin.read_exact(&mut seq[0..1]).is_err() ||
-               stdin.read_exact(&mut seq[1..2]).is_err() {
-                   return Ok(b'\x1b' as i32);
+        match buffer[0] {
+            b'\x1b' => Ok(self.read_escape_seq(&mut stdin)),
+            0 => Ok(CTRL_SPACE),
+            c @ 0xc0..=0xf7 => Ok(Self::read_utf8_char(&mut stdin, c)),
+            c => Ok(c as i32),
+        }
+    }
+
+    // Read the rest of a multi-byte UTF-8 char starting with `first`; an
+    // invalid sequence reads as 0, so it is dropped rather than guessed at.
+    fn read_utf8_char(stdin: &mut impl Read, first: u8) -> i32 {
+        let len = match first {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            _ => 4,
+        };
+        let mut bytes = vec![first];
+        for _ in 1..len {
+            match Self::read_byte(stdin) {
+                Some(b) => bytes.push(b),
+                None => break,
             }
-            if seq[0] == b'[' {
-                match seq[1] {
-                    x if b'0' < x && x < b'9' => {
-                        if stdin.read_exact(&mut seq[2..3]).is_err() {
-                            return Ok(b'\x1b' as i32)
-                        }
-                        if seq[2] == b'~' {
-                            match seq[1] {
-                                b'1' => return Ok(HOME_KEY),
-                                b'3' => return Ok(DEL_KEY),
-                                b'4' => return Ok(END_KEY),
-                                b'5' => return Ok(PAGE_UP),
-                                b'6' => return Ok(PAGE_DOWN),
-                                b'7' => return Ok(HOME_KEY),
-                                b'8' => return Ok(END_KEY),
-                                _ => {}
-                            }
-                        }
-                    }
-                    b'A' => return Ok(ARROW_UP),
-                    b'B' => return Ok(ARROW_DOWN),
-                    b'C' => return Ok(ARROW_RIGHT),
-                    b'D' => return Ok(ARROW_LEFT),
-                    b'H' => return Ok(HOME_KEY),
-                    b'F' => return Ok(END_KEY),
-                    _ => {}
-                }
-            } else if seq[0] == b'O' {
-                match seq[1] {
-                    b'H' => return Ok(HOME_KEY),
-                    b'F' => return Ok(END_KEY),
-                    _ => {}
+        }
+        match std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+            Some(c) => c as i32,
+            None => 0,
+        }
+    }
+
+    // Read the text of a bracketed paste, after `read_key` has returned
+    // PASTE_START, up to the end marker.
+    pub fn read_paste(&self) -> Result<String> {
+        let mut stdin = io::stdin().lock();
+        let mut bytes = Vec::new();
+        let mut buffer = [0u8; 1];
+        let mut timeouts = 0;
+        // byte by byte, so keys typed after the end marker are not pasted;
+        // stdin is buffered so this stays cheap
+        while !bytes.ends_with(PASTE_END) && timeouts < PASTE_TIMEOUTS {
+            match stdin.read(&mut buffer) {
+                Ok(0) => timeouts += 1,
+                Ok(_) => {
+                    bytes.push(buffer[0]);
+                    timeouts = 0;
                 }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e).context("Failed to read paste from stdin"),
             }
-            return Ok(b'\x1b' as i32)
         }
-        Ok(buffer[0] as i32)
+        if bytes.ends_with(PASTE_END) {
+            bytes.truncate(bytes.len() - PASTE_END.len());
+        }
+        Ok(Encoding::Utf8.decode(&bytes))
+    }
+
+    fn read_byte(stdin: &mut impl Read) -> Option<u8> {
+        let mut buffer = [0u8; 1];
+        stdin.read_exact(&mut buffer).ok().map(|_| buffer[0])
+    }
+
+    // Decode the rest of a sequence after ESC; a lone ESC reads as ESCAPE
+    // and a sequence that isn't understood as 0, so it is dropped.
+    fn read_escape_seq(&mut self, stdin: &mut impl Read) -> i32 {
+        let Some(first) = Self::read_byte(stdin) else {
+            return ESCAPE;
+        };
+        match first {
+            b'[' => self.read_csi_seq(stdin),
+            b'O' => match Self::read_byte(stdin) {
+                Some(b'A') => ARROW_UP,
+                Some(b'B') => ARROW_DOWN,
+                Some(b'C') => ARROW_RIGHT,
+                Some(b'D') => ARROW_LEFT,
+                Some(b'H') => HOME_KEY,
+                Some(b'F') => END_KEY,
+                _ => 0,
+            },
+            // ESC followed by a plain key is how terminals send Alt-<key>
+            c => ALT | c as i32,
+        }
+    }
+
+    // CSI sequences are `ESC [ params final`, e.g. `ESC [ 1 ; 2 A` for
+    // Shift-Up, where the second parameter encodes the modifiers. Mouse
+    // reports are kept in `self.mouse` and read as MOUSE.
+    fn read_csi_seq(&mut self, stdin: &mut impl Read) -> i32 {
+        let mut params = String::new();
+        let last = loop {
+            match Self::read_byte(stdin) {
+                Some(c @ 0x30..=0x3f) if params.len() < 32 => params.push(c as char),
+                Some(c @ 0x40..=0x7e) => break c,
+                _ => return 0,
+            }
+        };
+        if params.starts_with('<') {
+            self.mouse = MouseEvent::parse_sgr(&params, last);
+            return if self.mouse.is_some() { MOUSE } else { 0 };
+        }
+        let mut nums = params.split(';').map(|n| n.parse::<i32>().unwrap_or(1));
+        let code = nums.next().unwrap_or(1);
+        // xterm modifier parameter: 1 + (shift | alt << 1 | ctrl << 2)
+        let modifiers = nums.next().map_or(0, |m| {
+            let m = m - 1;
+            (if m & 1 != 0 { SHIFT } else { 0 })
+                | (if m & 2 != 0 { ALT } else { 0 })
+                | (if m & 4 != 0 { CTRL } else { 0 })
+        });
+        let key = match last {
+            b'A' => ARROW_UP,
+            b'B' => ARROW_DOWN,
+            b'C' => ARROW_RIGHT,
+            b'D' => ARROW_LEFT,
+            b'H' => HOME_KEY,
+            b'F' => END_KEY,
+            b'Z' => BACKTAB,
+            b'~' => match code {
+                1 | 7 => HOME_KEY,
+                3 => DEL_KEY,
+                4 | 8 => END_KEY,
+                5 => PAGE_UP,
+                6 => PAGE_DOWN,
+                200 => PASTE_START,
+                _ => return 0,
+            },
+            // `ESC [ 127 ; 5 u` from terminals that report Ctrl-Backspace
+            b'u' if code == BACKSPACE => BACKSPACE,
+            _ => return 0,
+        };
+        key | modifiers
     }
 
     fn get_cursor_position() -> Result<(usize, usize)> {
@@ -123,13 +260,53 @@ impl Editor {
         Ok((x, y))
     }
 
+    // No SA_RESTART, so a pending read is interrupted and the resize is
+    // picked up by the next redraw right away.
+    pub fn install_resize_handler() -> Result<()> {
+        let action = SigAction::new(
+            SigHandler::Handler(on_sigwinch),
+            SaFlags::empty(),
+            SigSet::empty(),
+        );
+        unsafe { sigaction(Signal::SIGWINCH, &action) }
+            .context("Failed to install SIGWINCH handler")?;
+        Ok(())
+    }
+
+    pub fn handle_resize(&mut self) -> Result<()> {
+        if !RESIZED.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let (screencols, screenrows) = Self::get_window_size()
+            .context("Failed to get window size")?;
+        self.set_window_size(screencols, screenrows);
+        self.row_off = self.row_off.min(self.buffer().rows.count());
+        self.col_off = self.col_off.min(self.rx);
+        Self::clear_screen();
+        Ok(())
+    }
+
+    pub fn set_window_size(&mut self, screencols: usize, screenrows: usize) {
+        // leave a line for the msg bar; windows take the rest
+        self.term_rows = screenrows.saturating_sub(1).max(2);
+        self.term_cols = screencols.max(1);
+        self.update_layout();
+    }
+
     pub fn get_window_size() -> Result<(usize, usize)> {
-        let mut stdout = io::stdout().lock();
+        let mut ws = libc::winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+        let stdout = io::stdout();
+        if unsafe { tiocgwinsz(stdout.as_raw_fd(), &mut ws) }.is_ok() && ws.ws_col != 0 {
+            return Ok((ws.ws_col as usize, ws.ws_row as usize));
+        }
+
+        // fall back to moving the cursor to the bottom-right corner
+        let mut stdout = stdout.lock();
         stdout.write_all(b"\x1b[999C\x1b[999B")?;
         stdout.flush()?;
 
         let ret = Self::get_cursor_position();
         Self::reposition_cursor();
-        return ret;
+        ret
     }
 }
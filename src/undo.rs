@@ -0,0 +1,177 @@
+use crate::editor::{Editor, EditorRow};
+use crate::file_io::LineEnding;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum EditKind {
+    InsertChar,
+    Other,
+    Joined, // undone and redone together with the edit before it
+}
+
+// A row's text and its own line ending, if any.
+type RowText = (String, Option<LineEnding>);
+
+// An edit replaces `old` rows starting at `at` with `new` rows.
+pub struct Edit {
+    kind: EditKind,
+    at: usize,
+    old: Vec<RowText>,
+    new: Vec<RowText>,
+    cursor_before: (usize, usize),
+    cursor_after: (usize, usize),
+}
+
+// Rows and cursor captured by `begin_edit`, completed by `commit_edit`.
+pub struct PendingEdit {
+    at: usize,
+    old: Vec<RowText>,
+    cursor_before: (usize, usize),
+}
+
+pub struct UndoStack {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    saved: Option<usize>, // undo depth matching the file on disk
+}
+
+impl UndoStack {
+    pub fn new() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            saved: Some(0),
+        }
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+    }
+
+    // The buffer no longer matches the file, whatever is undone or redone.
+    pub fn forget_saved(&mut self) {
+        self.saved = None;
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo.len())
+    }
+
+    fn push(&mut self, edit: Edit) {
+        if let Some(saved) = self.saved {
+            if saved > self.undo.len() {
+                // the saved state lived on the redo stack we are dropping
+                self.saved = None;
+            }
+        }
+        self.redo.clear();
+
+        // group consecutive character inserts into one undo step
+        if edit.kind == EditKind::InsertChar && !self.is_saved() {
+            if let Some(last) = self.undo.last_mut() {
+                if last.kind == EditKind::InsertChar
+                    && last.at == edit.at
+                    && last.new.len() == edit.old.len()
+                    && last.cursor_after == edit.cursor_before
+                {
+                    last.new = edit.new;
+                    last.cursor_after = edit.cursor_after;
+                    return;
+                }
+            }
+        }
+        self.undo.push(edit);
+    }
+}
+
+impl Editor {
+    pub fn begin_edit(&self, at: usize, count: usize) -> PendingEdit {
+        PendingEdit {
+            at,
+            old: self.row_texts(at, count),
+            cursor_before: (self.cx, self.cy),
+        }
+    }
+
+    pub fn commit_edit(&mut self, pending: PendingEdit, count: usize, kind: EditKind) {
+        let edit = Edit {
+            kind,
+            at: pending.at,
+            old: pending.old,
+            new: self.row_texts(pending.at, count),
+            cursor_before: pending.cursor_before,
+            cursor_after: (self.cx, self.cy),
+        };
+        self.rows_replaced(edit.at, edit.old.len(), edit.new.len());
+        let buffer = self.buffer_mut();
+        buffer.undo.push(edit);
+        buffer.dirty = true;
+    }
+
+    fn row_texts(&self, at: usize, count: usize) -> Vec<RowText> {
+        self.buffer().rows
+            .from_index(at)
+            .take(count)
+            .map(|row| (row.chars.clone(), row.line_ending))
+            .collect()
+    }
+
+    fn replace_rows(&mut self, at: usize, count: usize, lines: &[RowText]) {
+        let rows = &mut self.buffer_mut().rows;
+        for _ in 0..count {
+            rows.delete_line(at);
+        }
+        for (i, (line, line_ending)) in lines.iter().enumerate() {
+            let mut row = EditorRow::new(line);
+            row.line_ending = *line_ending;
+            rows.insert_line(at + i, row);
+        }
+        self.update_rows(at, lines.len());
+        self.rows_replaced(at, count, lines.len());
+    }
+
+    pub fn undo(&mut self) {
+        if self.refuse_read_only() {
+            return;
+        }
+        let Some(mut edit) = self.buffer_mut().undo.undo.pop() else {
+            self.set_status_msg("Nothing to undo");
+            return;
+        };
+        loop {
+            self.replace_rows(edit.at, edit.new.len(), &edit.old);
+            (self.cx, self.cy) = edit.cursor_before;
+            let joined = edit.kind == EditKind::Joined;
+            self.buffer_mut().undo.redo.push(edit);
+            if !joined {
+                break;
+            }
+            let Some(prev) = self.buffer_mut().undo.undo.pop() else {
+                break;
+            };
+            edit = prev;
+        }
+        let buffer = self.buffer_mut();
+        buffer.dirty = !buffer.undo.is_saved();
+    }
+
+    pub fn redo(&mut self) {
+        if self.refuse_read_only() {
+            return;
+        }
+        let Some(mut edit) = self.buffer_mut().undo.redo.pop() else {
+            self.set_status_msg("Nothing to redo");
+            return;
+        };
+        loop {
+            self.replace_rows(edit.at, edit.old.len(), &edit.new);
+            (self.cx, self.cy) = edit.cursor_after;
+            self.buffer_mut().undo.undo.push(edit);
+            let Some(next) = self.buffer_mut().undo.redo.pop_if(|next| next.kind == EditKind::Joined) else {
+                break;
+            };
+            edit = next;
+        }
+        let buffer = self.buffer_mut();
+        buffer.dirty = !buffer.undo.is_saved();
+    }
+}
@@ -0,0 +1,465 @@
+use crate::buffer::Buffer;
+use crate::editor::Editor;
+
+// how much Alt-= / Alt-- move a split, in percent
+const RESIZE_STEP: usize = 5;
+// smallest window: one text row plus the status bar, one column
+const MIN_HEIGHT: usize = 2;
+const MIN_WIDTH: usize = 1;
+
+// A screen area, in 0-based terminal cells.
+#[derive(Clone, Copy, Default)]
+pub struct Rect {
+    pub top: usize,
+    pub left: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Rect {
+    fn contains(&self, x: usize, y: usize) -> bool {
+        (self.top..self.top + self.height).contains(&y)
+            && (self.left..self.left + self.width).contains(&x)
+    }
+
+    // Text rows and columns of a window taking this rect, above its status
+    // bar.
+    fn text_size(&self) -> (usize, usize) {
+        (self.height.saturating_sub(1).max(1), self.width.max(1))
+    }
+}
+
+// A cursor and scroll position in a buffer.
+#[derive(Clone, Copy, Default)]
+pub struct View {
+    pub cx: usize,
+    pub cy: usize,
+    pub row_off: usize,
+    pub wrap_off: usize,
+    pub col_off: usize,
+}
+
+// A view onto a buffer. The focused window's view lives in the `Editor`
+// fields and is copied to and from its slot in `Editor::windows` when focus
+// moves.
+pub struct Window {
+    pub buffer: usize,
+    pub view: View,
+    pub rect: Rect, // including the status bar on its last row
+}
+
+impl Window {
+    pub fn new(buffer: usize, view: View) -> Self {
+        Self {
+            buffer,
+            view,
+            rect: Rect::default(),
+        }
+    }
+}
+
+// A window as it is drawn: its buffer and view, sized to its rect.
+pub struct Pane<'a> {
+    pub editor: &'a Editor,
+    pub window: &'a Window,
+    pub buffer: &'a Buffer,
+    pub view: View,
+    pub screenrows: usize,
+    pub screencols: usize,
+    pub focused: bool, // only the focused window shows the selection and search match
+}
+
+impl Pane<'_> {
+    // Render column of the cursor.
+    pub fn rx(&self) -> usize {
+        let rows = &self.buffer.rows;
+        if self.view.cy < rows.count() {
+            rows.get_line(self.view.cy).cx_to_rx(self.view.cx, self.editor.tab_stop)
+        } else {
+            0
+        }
+    }
+}
+
+// How the screen is divided between windows.
+#[derive(Clone)]
+pub enum Layout {
+    Window(usize),
+    Split {
+        side_by_side: bool,
+        percent: usize, // share of the first child
+        first: Box<Layout>,
+        second: Box<Layout>,
+    },
+}
+
+impl Layout {
+    fn contains(&self, id: usize) -> bool {
+        match self {
+            Layout::Window(w) => *w == id,
+            Layout::Split { first, second, .. } => first.contains(id) || second.contains(id),
+        }
+    }
+
+    // Window ids in screen order.
+    fn windows(&self, out: &mut Vec<usize>) {
+        match self {
+            Layout::Window(w) => out.push(*w),
+            Layout::Split { first, second, .. } => {
+                first.windows(out);
+                second.windows(out);
+            }
+        }
+    }
+
+    // Give each window its rect within `rect`, and collect the separator
+    // columns between windows side by side.
+    fn arrange(&self, rect: Rect, rects: &mut Vec<(usize, Rect)>, separators: &mut Vec<Rect>) {
+        match self {
+            Layout::Window(w) => rects.push((*w, rect)),
+            Layout::Split { side_by_side: false, percent, first, second } => {
+                let size = split_size(rect.height, *percent, MIN_HEIGHT, 0);
+                first.arrange(Rect { height: size, ..rect }, rects, separators);
+                second.arrange(
+                    Rect { top: rect.top + size, height: rect.height - size, ..rect },
+                    rects,
+                    separators,
+                );
+            }
+            Layout::Split { side_by_side: true, percent, first, second } => {
+                let size = split_size(rect.width, *percent, MIN_WIDTH, 1);
+                first.arrange(Rect { width: size, ..rect }, rects, separators);
+                let rest = rect.width.saturating_sub(size + 1);
+                if rest > 0 {
+                    separators.push(Rect { left: rect.left + size, width: 1, ..rect });
+                }
+                second.arrange(
+                    Rect { left: rect.left + size + 1, width: rest, ..rect },
+                    rects,
+                    separators,
+                );
+            }
+        }
+    }
+
+    // Replace window `id` with a split of it and `new`.
+    fn split(&mut self, id: usize, new: usize, side_by_side: bool) {
+        match self {
+            Layout::Window(w) if *w == id => {
+                *self = Layout::Split {
+                    side_by_side,
+                    percent: 50,
+                    first: Box::new(Layout::Window(id)),
+                    second: Box::new(Layout::Window(new)),
+                };
+            }
+            Layout::Window(_) => {}
+            Layout::Split { first, second, .. } => {
+                first.split(id, new, side_by_side);
+                second.split(id, new, side_by_side);
+            }
+        }
+    }
+
+    // The layout without window `id`, its sibling taking its space.
+    fn without(&self, id: usize) -> Layout {
+        match self {
+            Layout::Split { first, second, .. } if matches!(**first, Layout::Window(w) if w == id) => {
+                (**second).clone()
+            }
+            Layout::Split { first, second, .. } if matches!(**second, Layout::Window(w) if w == id) => {
+                (**first).clone()
+            }
+            Layout::Split { side_by_side, percent, first, second } => Layout::Split {
+                side_by_side: *side_by_side,
+                percent: *percent,
+                first: Box::new(first.without(id)),
+                second: Box::new(second.without(id)),
+            },
+            Layout::Window(w) => Layout::Window(*w),
+        }
+    }
+
+    // Window ids after `removed` move down by one.
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            Layout::Window(w) if *w > removed => *w -= 1,
+            Layout::Window(_) => {}
+            Layout::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+
+    // Grow (or shrink) window `id` within the innermost split holding it.
+    fn resize(&mut self, id: usize, grow: bool) -> bool {
+        let Layout::Split { percent, first, second, .. } = self else {
+            return false;
+        };
+        if first.resize(id, grow) || second.resize(id, grow) {
+            return true;
+        }
+        let grow_first = match (first.contains(id), second.contains(id)) {
+            (true, _) => grow,
+            (_, true) => !grow,
+            _ => return false,
+        };
+        *percent = if grow_first {
+            (*percent + RESIZE_STEP).min(90)
+        } else {
+            percent.saturating_sub(RESIZE_STEP).max(10)
+        };
+        true
+    }
+}
+
+// Size of the first part when splitting `total` cells at `percent`, leaving
+// `gap` cells between the parts and `min` for each where possible.
+fn split_size(total: usize, percent: usize, min: usize, gap: usize) -> usize {
+    let size = total * percent / 100;
+    if total >= 2 * min + gap {
+        size.clamp(min, total - min - gap)
+    } else {
+        size.min(total)
+    }
+}
+
+impl Editor {
+    // The focused window's view.
+    pub fn view(&self) -> View {
+        View {
+            cx: self.cx,
+            cy: self.cy,
+            row_off: self.row_off,
+            wrap_off: self.wrap_off,
+            col_off: self.col_off,
+        }
+    }
+
+    // Move the focused window to `view`, keeping the cursor in the buffer.
+    pub fn set_view(&mut self, view: View) {
+        (self.cx, self.cy, self.row_off, self.wrap_off, self.col_off) =
+            (view.cx, view.cy, view.row_off, view.wrap_off, view.col_off);
+        self.clamp_cursor();
+    }
+
+    // Window `id` as it is to be drawn.
+    pub fn pane(&self, id: usize) -> Pane<'_> {
+        let window = &self.windows[id];
+        let focused = id == self.current_window;
+        let (screenrows, screencols) = window.rect.text_size();
+        Pane {
+            editor: self,
+            window,
+            buffer: &self.buffers[window.buffer],
+            view: if focused { self.view() } else { window.view },
+            screenrows,
+            screencols,
+            focused,
+        }
+    }
+
+    pub fn current_pane(&self) -> Pane<'_> {
+        self.pane(self.current_window)
+    }
+
+    pub fn focus_window(&mut self, i: usize) {
+        if i == self.current_window {
+            return;
+        }
+        self.windows[self.current_window].view = self.view();
+        self.current_window = i;
+        self.set_view(self.windows[i].view);
+        self.fit_window_size();
+        self.buffer_mut().selection = None;
+        self.search_hl = None;
+    }
+
+    // The layout's window ids in screen order.
+    pub fn window_order(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        self.layout.windows(&mut order);
+        order
+    }
+
+    pub fn next_window(&mut self) {
+        let order = self.window_order();
+        if order.len() == 1 {
+            self.set_status_msg("No other windows");
+            return;
+        }
+        let pos = order.iter().position(|&w| w == self.current_window).unwrap_or(0);
+        self.focus_window(order[(pos + 1) % order.len()]);
+    }
+
+    // Split the focused window; the new window shows the same buffer at the
+    // same position and gets the focus.
+    pub fn split_window(&mut self, side_by_side: bool) {
+        let rect = self.windows[self.current_window].rect;
+        let fits = if side_by_side {
+            rect.width > 2 * MIN_WIDTH // and the separator
+        } else {
+            rect.height >= 2 * MIN_HEIGHT
+        };
+        if !fits {
+            self.set_status_msg("Window too small to split");
+            return;
+        }
+        self.windows.push(Window::new(self.current_buffer(), self.view()));
+        let new = self.windows.len() - 1;
+        self.layout.split(self.current_window, new, side_by_side);
+        self.update_layout();
+        self.focus_window(new);
+    }
+
+    pub fn close_window(&mut self) {
+        if self.windows.len() == 1 {
+            self.set_status_msg("Can't close the last window");
+            return;
+        }
+        let closed = self.current_window;
+        let order = self.window_order();
+        let pos = order.iter().position(|&w| w == closed).unwrap_or(0);
+        let next = if pos > 0 { order[pos - 1] } else { order[1] };
+        self.focus_window(next);
+        self.layout = self.layout.without(closed);
+        self.layout.renumber(closed);
+        self.windows.remove(closed);
+        if self.current_window > closed {
+            self.current_window -= 1;
+        }
+        self.update_layout();
+    }
+
+    pub fn resize_window(&mut self, grow: bool) {
+        if self.layout.resize(self.current_window, grow) {
+            self.update_layout();
+        }
+    }
+
+    // Lay the windows out over the screen above the message bar.
+    pub fn update_layout(&mut self) {
+        let screen = Rect { top: 0, left: 0, height: self.term_rows, width: self.term_cols };
+        let mut rects = Vec::new();
+        let mut separators = Vec::new();
+        self.layout.arrange(screen, &mut rects, &mut separators);
+        for (id, rect) in rects {
+            self.windows[id].rect = rect;
+        }
+        self.separators = separators;
+        self.fit_window_size();
+    }
+
+    // Size the text area to the focused window.
+    fn fit_window_size(&mut self) {
+        (self.screenrows, self.screencols) = self.windows[self.current_window].rect.text_size();
+    }
+
+    // Window under a screen cell, if any.
+    pub fn window_at(&self, x: usize, y: usize) -> Option<usize> {
+        (0..self.windows.len()).find(|&i| self.windows[i].rect.contains(x, y))
+    }
+
+    // Rows `at..at + removed` of the current buffer were replaced by
+    // `inserted` rows: keep the other windows on it pointing at the same
+    // text, and inside the buffer.
+    pub fn rows_replaced(&mut self, at: usize, removed: usize, inserted: usize) {
+        let current = self.current_buffer();
+        let rows = &self.buffers[current].rows;
+        let count = rows.count();
+        for (i, window) in self.windows.iter_mut().enumerate() {
+            if i == self.current_window || window.buffer != current {
+                continue;
+            }
+            let view = &mut window.view;
+            for row in [&mut view.cy, &mut view.row_off] {
+                if *row >= at + removed {
+                    *row = *row + inserted - removed;
+                } else if *row >= at {
+                    *row = (*row).min(at + inserted.saturating_sub(1));
+                }
+                *row = (*row).min(count);
+            }
+            view.wrap_off = 0;
+            if view.cy < count {
+                view.cx = view.cx.min(rows.get_line(view.cy).len());
+            } else {
+                view.cx = 0;
+            }
+        }
+    }
+
+    // Buffer `closed` was removed and the ones after it moved down: windows
+    // showing it show buffer `replacement` from the top instead.
+    pub fn buffer_removed(&mut self, closed: usize, replacement: usize) {
+        for window in &mut self.windows {
+            if window.buffer == closed {
+                window.buffer = replacement;
+                window.view = View::default();
+            } else if window.buffer > closed {
+                window.buffer -= 1;
+            }
+        }
+    }
+
+    // Keep the cursor within the buffer and off the middle of a grapheme.
+    pub fn clamp_cursor(&mut self) {
+        let rows = &self.buffer().rows;
+        let cy = self.cy.min(rows.count());
+        self.cx = if cy < rows.count() {
+            let line = rows.get_line(cy);
+            line.grapheme_start(self.cx.min(line.len()))
+        } else {
+            0
+        };
+        self.cy = cy;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(layout: &Layout) -> Vec<usize> {
+        let mut out = Vec::new();
+        layout.windows(&mut out);
+        out
+    }
+
+    // (id, top, left, height, width) of each window on a 20x80 screen
+    fn rects(layout: &Layout) -> Vec<(usize, usize, usize, usize, usize)> {
+        let mut rects = Vec::new();
+        layout.arrange(Rect { top: 0, left: 0, height: 20, width: 80 }, &mut rects, &mut Vec::new());
+        rects.into_iter().map(|(id, r)| (id, r.top, r.left, r.height, r.width)).collect()
+    }
+
+    #[test]
+    fn split_size_keeps_the_minimum() {
+        assert_eq!(split_size(20, 50, 2, 0), 10);
+        assert_eq!(split_size(21, 50, 1, 1), 10);
+        assert_eq!(split_size(20, 95, 2, 0), 18);
+        assert_eq!(split_size(20, 5, 2, 0), 2);
+        // too small for both minimums
+        assert_eq!(split_size(3, 50, 2, 0), 1);
+        assert_eq!(split_size(0, 50, 2, 0), 0);
+    }
+
+    #[test]
+    fn without_gives_the_space_to_the_sibling() {
+        let mut layout = Layout::Window(0);
+        layout.split(0, 1, false);
+        layout.split(1, 2, true);
+        assert_eq!(ids(&layout), [0, 1, 2]);
+
+        let closed = layout.without(1);
+        assert_eq!(ids(&closed), [0, 2]);
+        assert_eq!(rects(&closed), [(0, 0, 0, 10, 80), (2, 10, 0, 10, 80)]);
+
+        let closed = layout.without(0);
+        assert_eq!(rects(&closed), [(1, 0, 0, 20, 40), (2, 0, 41, 20, 39)]);
+
+        let closed = closed.without(2);
+        assert!(matches!(closed, Layout::Window(1)));
+    }
+}
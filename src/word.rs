@@ -0,0 +1,134 @@
+use crate::editor::Editor;
+use crate::operations::display_width;
+
+// Letters, digits and underscores make up words; combining marks belong to
+// the word of their base char.
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || display_width(c) == 0
+}
+
+impl Editor {
+    // The chars of `row`, or none past the last row.
+    fn row_chars(&self, row: usize) -> Vec<char> {
+        if row < self.buffer().rows.count() {
+            self.buffer().rows.get_line(row).chars.chars().collect()
+        } else {
+            Vec::new()
+        }
+    }
+
+    // The char before `pos` and the position before it, with '\n' between
+    // rows. `chars` holds the chars of `pos`'s row and follows the step.
+    fn step_back(&self, chars: &mut Vec<char>, (row, col): (usize, usize)) -> Option<(char, (usize, usize))> {
+        if col == 0 || row >= self.buffer().rows.count() {
+            if row == 0 {
+                return None;
+            }
+            *chars = self.row_chars(row - 1);
+            return Some(('\n', (row - 1, chars.len())));
+        }
+        let c = *chars.get(col - 1)?;
+        Some((c, (row, col - 1)))
+    }
+
+    // The char after `pos` and the position after it, with '\n' between
+    // rows. `chars` holds the chars of `pos`'s row and follows the step.
+    fn step_forward(&self, chars: &mut Vec<char>, (row, col): (usize, usize)) -> Option<(char, (usize, usize))> {
+        if row >= self.buffer().rows.count() {
+            return None;
+        }
+        match chars.get(col) {
+            Some(&c) => Some((c, (row, col + 1))),
+            None if row + 1 < self.buffer().rows.count() => {
+                *chars = self.row_chars(row + 1);
+                Some(('\n', (row + 1, 0)))
+            }
+            None => None,
+        }
+    }
+
+    // Start of the word before the cursor, skipping any non-word chars and
+    // row breaks in between.
+    pub fn word_start_before(&self) -> (usize, usize) {
+        let mut pos = (self.cy, self.cx);
+        let mut in_word = false;
+        let mut chars = self.row_chars(pos.0);
+        while let Some((c, prev)) = self.step_back(&mut chars, pos) {
+            if is_word_char(c) {
+                in_word = true;
+            } else if in_word {
+                break;
+            }
+            pos = prev;
+        }
+        pos
+    }
+
+    // End of the word after the cursor, skipping any non-word chars and row
+    // breaks in between.
+    pub fn word_end_after(&self) -> (usize, usize) {
+        let mut pos = (self.cy, self.cx);
+        let mut in_word = false;
+        let mut chars = self.row_chars(pos.0);
+        while let Some((c, next)) = self.step_forward(&mut chars, pos) {
+            if is_word_char(c) {
+                in_word = true;
+            } else if in_word {
+                break;
+            }
+            pos = next;
+        }
+        pos
+    }
+
+    // Delete from the cursor to the start or end of a word, or the selection
+    // if there is one.
+    pub fn delete_word(&mut self, forward: bool) {
+        if self.delete_selection() {
+            return;
+        }
+        let cursor = (self.cy, self.cx);
+        if forward {
+            let end = self.word_end_after();
+            if end != cursor {
+                self.delete_range(cursor, end);
+            }
+        } else {
+            let start = self.word_start_before();
+            if start != cursor {
+                self.delete_range(start, cursor);
+            }
+        }
+    }
+
+    fn is_blank_row(&self, row: usize) -> bool {
+        self.buffer().rows.get_line(row).chars.trim().is_empty()
+    }
+
+    // Row of the blank line before or after the current paragraph, or the
+    // first or last row when there is none.
+    pub fn paragraph_boundary(&self, forward: bool) -> usize {
+        let count = self.buffer().rows.count();
+        if count == 0 {
+            return 0;
+        }
+        let mut row = self.cy.min(count - 1);
+        if forward {
+            while row < count && self.is_blank_row(row) {
+                row += 1;
+            }
+            while row < count && !self.is_blank_row(row) {
+                row += 1;
+            }
+            row.min(count - 1)
+        } else {
+            while row > 0 && self.is_blank_row(row) {
+                row -= 1;
+            }
+            while row > 0 && !self.is_blank_row(row) {
+                row -= 1;
+            }
+            row
+        }
+    }
+}
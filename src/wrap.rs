@@ -0,0 +1,237 @@
+use crate::editor::{Editor, EditorRow};
+use crate::operations::display_width;
+use crate::window::{Pane, View};
+
+impl EditorRow {
+    // Render columns where each visual line of the row starts when wrapped
+    // to `width` columns, breaking after a space where possible and never
+    // inside a wide char.
+    pub fn wrap_starts(&self, width: usize) -> Vec<usize> {
+        let mut starts = vec![0];
+        let mut start = 0;
+        let mut col = 0;
+        let mut after_space = None; // column after the last space on the line
+        for c in self.render.chars() {
+            let char_width = display_width(c);
+            while col + char_width - start > width && col > start {
+                start = match after_space {
+                    Some(after) if after > start => after,
+                    _ => col,
+                };
+                starts.push(start);
+                after_space = None;
+            }
+            col += char_width;
+            if c == ' ' {
+                after_space = Some(col);
+            }
+        }
+        starts
+    }
+}
+
+impl Pane<'_> {
+    // Columns available to wrapped text; the last one is kept for the
+    // continuation marker.
+    pub fn wrap_width(&self) -> usize {
+        self.text_cols().saturating_sub(1).max(1)
+    }
+
+    // Visual line starts of buffer row `row`; a row past the end of the
+    // buffer has one empty visual line.
+    pub fn row_wrap_starts(&self, row: usize) -> Vec<usize> {
+        if row < self.buffer.rows.count() {
+            self.buffer.rows.get_line(row).wrap_starts(self.wrap_width())
+        } else {
+            vec![0]
+        }
+    }
+
+    // The cursor's visual line within its row and its column on that line.
+    pub fn cursor_visual_pos(&self) -> (usize, usize) {
+        let starts = self.row_wrap_starts(self.view.cy);
+        let rx = self.rx();
+        let line = visual_line_at(&starts, rx);
+        (line, rx - starts[line])
+    }
+
+    // The view scrolled in visual lines so the cursor's visual line is on
+    // screen.
+    pub fn scrolled_wrapped(&self) -> View {
+        let mut view = self.view;
+        view.col_off = 0;
+        view.wrap_off = view.wrap_off.min(self.row_wrap_starts(view.row_off).len() - 1);
+        let (line, _) = self.cursor_visual_pos();
+        if (view.cy, line) < (view.row_off, view.wrap_off) {
+            (view.row_off, view.wrap_off) = (view.cy, line);
+        }
+
+        // the highest top that still shows the cursor
+        let (mut row, mut off) = (view.cy, line);
+        let mut remaining = self.screenrows - 1;
+        while remaining > 0 {
+            if off >= remaining {
+                off -= remaining;
+                break;
+            }
+            if row == 0 {
+                off = 0;
+                break;
+            }
+            remaining -= off + 1;
+            row -= 1;
+            off = self.row_wrap_starts(row).len() - 1;
+        }
+        if (row, off) > (view.row_off, view.wrap_off) {
+            (view.row_off, view.wrap_off) = (row, off);
+        }
+        view
+    }
+
+    // Screen row of the cursor, counting visual lines from the top.
+    pub fn cursor_screen_row(&self) -> usize {
+        let (line, _) = self.cursor_visual_pos();
+        let rows: usize = (self.view.row_off..self.view.cy)
+            .map(|row| self.row_wrap_starts(row).len())
+            .sum();
+        rows + line - self.view.wrap_off
+    }
+}
+
+// Visual line of a row holding render column `rx`.
+fn visual_line_at(starts: &[usize], rx: usize) -> usize {
+    starts.iter().rposition(|&start| start <= rx).unwrap_or(0)
+}
+
+impl Editor {
+    pub fn toggle_soft_wrap(&mut self) {
+        self.soft_wrap = !self.soft_wrap;
+        self.wrap_off = 0;
+        self.col_off = 0;
+        self.set_status_msg(if self.soft_wrap { "Soft wrap on" } else { "Soft wrap off" });
+    }
+
+    pub fn row_wrap_starts(&self, row: usize) -> Vec<usize> {
+        self.current_pane().row_wrap_starts(row)
+    }
+
+    pub fn cursor_visual_pos(&self) -> (usize, usize) {
+        self.current_pane().cursor_visual_pos()
+    }
+
+    // Buffer row and visual line shown at screen row `y`, if any.
+    pub fn visual_line_at_screen(&self, y: usize) -> Option<(usize, usize)> {
+        let (mut row, mut line) = (self.row_off, self.wrap_off);
+        for _ in 0..y {
+            if line + 1 < self.row_wrap_starts(row).len() {
+                line += 1;
+            } else {
+                row += 1;
+                line = 0;
+            }
+            if row >= self.buffer().rows.count() {
+                return None;
+            }
+        }
+        Some((row, line))
+    }
+
+    // Buffer row shown on the last screen line, at most one past the end of
+    // the buffer.
+    pub fn last_screen_row(&self) -> usize {
+        if self.soft_wrap {
+            self.visual_line_at_screen(self.screenrows - 1)
+                .map_or(self.buffer().rows.count(), |(row, _)| row)
+        } else {
+            (self.row_off + self.screenrows - 1).min(self.buffer().rows.count())
+        }
+    }
+
+    // The char on visual line `line` of `row` closest to column `col` of that
+    // line, never past its end.
+    pub fn cx_on_visual_line(&self, row: usize, line: usize, col: usize) -> usize {
+        if row >= self.buffer().rows.count() {
+            return 0;
+        }
+        let starts = self.row_wrap_starts(row);
+        let row = self.buffer().rows.get_line(row);
+        let rx = starts[line] + col;
+        match starts.get(line + 1) {
+            Some(&next) => row.rx_to_cx(rx.min(next - 1), self.tab_stop),
+            None => row.rx_to_cx(rx, self.tab_stop),
+        }
+    }
+
+    // Move the cursor to screen line `y`, or the end of the buffer when the
+    // text stops above it, keeping its column.
+    pub fn move_to_screen_line(&mut self, y: usize) {
+        self.update_rx();
+        let (_, col) = self.cursor_visual_pos();
+        match self.visual_line_at_screen(y) {
+            Some((row, line)) => {
+                self.cy = row;
+                self.cx = self.cx_on_visual_line(row, line, col);
+            }
+            None => self.cy = self.buffer().rows.count(),
+        }
+    }
+
+    // Move the cursor one visual line up or down, keeping its column.
+    pub fn move_visual_line(&mut self, down: bool) {
+        self.update_rx();
+        let (line, col) = self.cursor_visual_pos();
+        let target = if down {
+            if line + 1 < self.row_wrap_starts(self.cy).len() {
+                (self.cy, line + 1)
+            } else if self.cy + 1 < self.buffer().rows.count() {
+                (self.cy + 1, 0)
+            } else {
+                return;
+            }
+        } else if line > 0 {
+            (self.cy, line - 1)
+        } else if self.cy > 0 {
+            (self.cy - 1, self.row_wrap_starts(self.cy - 1).len() - 1)
+        } else {
+            return;
+        };
+        self.cy = target.0;
+        self.cx = self.cx_on_visual_line(target.0, target.1, col);
+        self.update_rx();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::operations::TAB_STOP;
+
+    fn starts(text: &str, width: usize) -> Vec<usize> {
+        let mut row = EditorRow::new(text);
+        row.update_render(TAB_STOP);
+        row.wrap_starts(width)
+    }
+
+    #[test]
+    fn breaks_after_spaces() {
+        assert_eq!(starts("", 4), [0]);
+        assert_eq!(starts("abcd", 4), [0]);
+        assert_eq!(starts("ab cd ef", 6), [0, 6]);
+        assert_eq!(starts("ab cdef", 4), [0, 3]);
+    }
+
+    #[test]
+    fn breaks_long_words_anywhere() {
+        assert_eq!(starts("abcdefghij", 4), [0, 4, 8]);
+        assert_eq!(starts("a bcdefgh", 3), [0, 2, 5, 8]);
+    }
+
+    #[test]
+    fn keeps_wide_chars_whole() {
+        // each takes two columns
+        assert_eq!(starts("日本語", 3), [0, 2, 4]);
+        assert_eq!(starts("a日本", 2), [0, 1, 3]);
+        // too wide for the window at all, still one per line
+        assert_eq!(starts("日本", 1), [0, 2]);
+    }
+}